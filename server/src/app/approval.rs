@@ -0,0 +1,72 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use serde::Deserialize;
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+
+use super::*;
+use crate::workspace_manager::{ApprovalEvent, ApprovalQueue, ApprovalRequest};
+
+#[derive(Debug, Deserialize)]
+struct ResolveApprovalRequest {
+    approved: bool,
+}
+
+/// Router of the approval channel, only served on a loopback address, where the IDE lists pending
+/// approvals, follows new ones and resolves them.
+///
+/// - `GET /approvals`: pending approvals, oldest first.
+/// - `GET /approvals/events`: server-sent `requested` and `resolved` events.
+/// - `POST /approvals/{id}` with `{"approved": true|false}`: resolve an approval.
+///
+/// Requests must carry the `--auth-token` as a bearer token, which only the IDE knows.
+pub fn approval_router(approvals: ApprovalQueue, auth_token: String) -> axum::Router {
+    let token_store = Arc::new(simple_oauth::SimpleOauthTokenStore::new(vec![auth_token]));
+
+    axum::Router::new()
+        .route("/approvals", axum::routing::get(handle_get_approvals))
+        .route(
+            "/approvals/events",
+            axum::routing::get(handle_get_approval_events),
+        )
+        .route("/approvals/{id}", axum::routing::post(handle_post_approval))
+        .layer(axum::middleware::from_fn_with_state(
+            token_store,
+            simple_oauth::simple_oauth_middleware,
+        ))
+        .with_state(approvals)
+}
+
+async fn handle_get_approvals(
+    State(approvals): State<ApprovalQueue>,
+) -> Json<Vec<ApprovalRequest>> {
+    Json(approvals.list())
+}
+
+async fn handle_get_approval_events(
+    State(approvals): State<ApprovalQueue>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = BroadcastStream::new(approvals.subscribe()).filter_map(|event| {
+        // Lagging listeners miss events, but can list the pending approvals again.
+        let event: ApprovalEvent = event.ok()?;
+        Event::default().json_data(event).ok().map(Ok)
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn handle_post_approval(
+    State(approvals): State<ApprovalQueue>,
+    Path(id): Path<String>,
+    Json(ResolveApprovalRequest { approved }): Json<ResolveApprovalRequest>,
+) -> StatusCode {
+    match approvals.resolve(&id, approved) {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::Request, middleware::Next, response::Response};
+
+use super::*;
+
+/// How clients authenticate on `/mcp`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum AuthMode {
+    /// Static bearer tokens (`--auth-token` and `--auth-tokens-file`).
+    #[default]
+    Bearer,
+    /// Access tokens issued by the built-in OAuth server.
+    Oauth,
+    /// Either of them.
+    Both,
+}
+
+/// State of [`bearer_or_oauth_middleware`], used in [`AuthMode::Both`].
+#[derive(Clone)]
+pub struct AuthState {
+    pub token_store: Arc<simple_oauth::SimpleOauthTokenStore>,
+    pub oauth_store: Arc<oauth::OauthStore>,
+}
+
+pub async fn bearer_or_oauth_middleware(
+    State(auth_state): State<AuthState>,
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let Some(token) = simple_oauth::extract_token(request.headers()) else {
+        return oauth::unauthorized_response(false);
+    };
+
+    // Static tokens are checked first since they don't need a lock.
+    let is_authorized =
+        auth_state.token_store.is_valid(&token) || auth_state.oauth_store.is_valid_token(&token);
+    match is_authorized {
+        true => next.run(request).await,
+        false => oauth::unauthorized_response(true),
+    }
+}
@@ -1,6 +1,14 @@
+mod approval;
+mod auth;
 mod constants;
 mod oauth;
+mod oauth_storage;
 mod router;
 mod simple_oauth;
+mod telemetry;
 
-pub use router::router;
+pub use approval::approval_router;
+pub use auth::AuthMode;
+pub use router::{RouterOptions, router};
+pub use simple_oauth::load_tokens_file;
+pub use telemetry::serve_telemetry;
@@ -1,7 +1,6 @@
 use std::{collections::HashMap, sync::Arc};
 
 use super::*;
-use anyhow::Result;
 use askama::Template;
 use axum::{
     Json,
@@ -11,54 +10,106 @@ use axum::{
     middleware::Next,
     response::{Html, IntoResponse, Redirect, Response},
 };
+use base64::{
+    Engine,
+    engine::general_purpose::{
+        STANDARD as BASE64_STANDARD, URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD,
+    },
+};
 use rand::{Rng, distr::Alphanumeric};
 use rmcp::transport::auth::{
-    AuthorizationMetadata, ClientRegistrationRequest, ClientRegistrationResponse, OAuthClientConfig,
+    AuthorizationMetadata, ClientRegistrationRequest, ClientRegistrationResponse,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+const ACCESS_TOKEN_LIFETIME_SECS: u64 = 3600;
+const AUTH_CODE_LIFETIME_SECS: i64 = 60;
+const REFRESH_TOKEN_LIFETIME_SECS: i64 = 30 * 24 * 3600;
+/// Interval between removals of the expired codes and tokens.
+const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
 // A easy way to manage MCP OAuth Store for managing tokens and sessions
 #[derive(Clone, Debug)]
 pub struct OauthStore {
-    clients: Arc<tokio::sync::RwLock<HashMap<String, OAuthClientConfig>>>,
-    auth_sessions: Arc<tokio::sync::RwLock<HashMap<String, AuthSession>>>,
-    access_tokens: Arc<tokio::sync::RwLock<HashMap<String, McpAccessToken>>>,
+    storage: Arc<dyn oauth_storage::OauthStorage>,
 }
 impl OauthStore {
-    pub fn new() -> Self {
-        let mut clients = HashMap::new();
-        clients.insert(
-            "mcp-client".to_string(),
-            OAuthClientConfig {
-                client_id: "mcp-client".to_string(),
-                client_secret: Some("mcp-client-secret".to_string()),
-                scopes: vec!["profile".to_string(), "email".to_string()],
-                redirect_uri: "http://localhost:8080/callback".to_string(),
-            },
-        );
+    pub fn new(storage: Arc<dyn oauth_storage::OauthStorage>) -> anyhow::Result<Self> {
+        update_state(&*storage, |state| {
+            state
+                .clients
+                .entry("mcp-client".to_string())
+                .or_insert_with(|| OauthClient {
+                    client_id: "mcp-client".to_string(),
+                    client_secret: Some("mcp-client-secret".to_string()),
+                    scopes: vec!["profile".to_string(), "email".to_string()],
+                    redirect_uri: "http://localhost:8080/callback".to_string(),
+                    is_public: false,
+                });
+        })
+        .map_err(anyhow::Error::msg)?;
 
-        Self {
-            clients: Arc::new(tokio::sync::RwLock::new(clients)),
-            auth_sessions: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
-            access_tokens: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
-        }
+        Ok(Self { storage })
     }
 
-    async fn validate_client(
+    fn read<T>(&self, read: impl FnOnce(&oauth_storage::OauthState) -> T) -> Result<T, String> {
+        let mut read = Some(read);
+        let mut output = None;
+        self.storage
+            .read(&mut |state| output = read.take().map(|read| read(state)))
+            .map_err(|error| {
+                error!("Failed to read the OAuth store: {error:#}");
+                format!("Failed to read the OAuth store: {error}")
+            })?;
+
+        output.ok_or_else(|| "The OAuth store didn't read the state".to_string())
+    }
+
+    /// Apply `update` off the async runtime, since the file storage waits for other servers to
+    /// release its lock and syncs the file.
+    async fn update<T: Send + 'static>(
         &self,
-        client_id: &str,
-        redirect_uri: &str,
-    ) -> Option<OAuthClientConfig> {
-        let clients = self.clients.read().await;
-        if let Some(client) = clients.get(client_id) {
-            if client.redirect_uri.contains(&redirect_uri.to_string()) {
-                return Some(client.clone());
-            }
-        }
-        None
+        update: impl FnOnce(&mut oauth_storage::OauthState) -> T + Send + 'static,
+    ) -> Result<T, String> {
+        let storage = self.storage.clone();
+        tokio::task::spawn_blocking(move || update_state(&*storage, update))
+            .await
+            .map_err(|error| format!("Failed to update the OAuth store: {error}"))?
+    }
+
+    /// Whether `client_secret` is the secret of `client_id`, public clients having none since they
+    /// use PKCE instead.
+    fn authenticate_client(&self, client_id: &str, client_secret: &str) -> bool {
+        self.read(|state| {
+            state
+                .clients
+                .get(client_id)
+                .is_some_and(|client| client.is_authenticated_by(client_secret))
+        })
+        .unwrap_or(false)
+    }
+
+    fn validate_client(&self, client_id: &str, redirect_uri: &str) -> Option<OauthClient> {
+        self.read(|state| {
+            state
+                .clients
+                .get(client_id)
+                .filter(|client| client.redirect_uri.contains(&redirect_uri.to_string()))
+                .cloned()
+        })
+        .ok()?
+    }
+
+    async fn register_client(&self, client: OauthClient) -> Result<(), String> {
+        self.update(|state| {
+            state.clients.insert(client.client_id.clone(), client);
+        })
+        .await
     }
 
     async fn create_auth_session(
@@ -66,21 +117,27 @@ impl OauthStore {
         client_id: String,
         scope: Option<String>,
         state: Option<String>,
+        code_challenge: Option<CodeChallenge>,
         session_id: String,
-    ) -> String {
+    ) -> Result<String, String> {
         let session = AuthSession {
             client_id,
             scope,
             _state: state,
-            _created_at: chrono::Utc::now(),
+            created_at: chrono::Utc::now(),
             auth_token: None,
+            grant_id: None,
+            code_challenge,
         };
 
-        self.auth_sessions
-            .write()
-            .await
-            .insert(session_id.clone(), session);
-        session_id
+        self.update({
+            let session_id = session_id.clone();
+            move |state| {
+                state.auth_sessions.insert(session_id, session);
+            }
+        })
+        .await?;
+        Ok(session_id)
     }
 
     async fn update_auth_session_token(
@@ -88,56 +145,273 @@ impl OauthStore {
         session_id: &str,
         token: AuthToken,
     ) -> Result<(), String> {
-        let mut sessions = self.auth_sessions.write().await;
-        if let Some(session) = sessions.get_mut(session_id) {
-            session.auth_token = Some(token);
-            Ok(())
-        } else {
-            Err("Session not found".to_string())
-        }
+        let session_id = session_id.to_string();
+        self.update(move |state| {
+            if let Some(session) = state.auth_sessions.get_mut(&session_id) {
+                session.auth_token = Some(token);
+                Ok(())
+            } else {
+                Err("Session not found".to_string())
+            }
+        })
+        .await?
     }
 
-    async fn create_mcp_token(&self, session_id: &str) -> Result<McpAccessToken, String> {
-        let sessions = self.auth_sessions.read().await;
-        if let Some(session) = sessions.get(session_id) {
-            if let Some(auth_token) = &session.auth_token {
-                let access_token = format!("mcp-token-{}", Uuid::new_v4());
-                let token = McpAccessToken {
-                    access_token: access_token.clone(),
-                    token_type: "Bearer".to_string(),
-                    expires_in: 3600,
-                    refresh_token: format!("mcp-refresh-{}", Uuid::new_v4()),
-                    scope: session.scope.clone(),
-                    auth_token: auth_token.clone(),
-                    client_id: session.client_id.clone(),
-                };
-
-                self.access_tokens
-                    .write()
-                    .await
-                    .insert(access_token.clone(), token.clone());
-                Ok(token)
-            } else {
-                Err("No third-party token available for session".to_string())
+    /// Redeem the authorization code of `session_id`, which can only be done once.
+    ///
+    /// The outer error is a failure of the store, the inner one a rejection of the code.
+    async fn create_mcp_token(
+        &self,
+        session_id: &str,
+        client_id: &str,
+        code_verifier: Option<&str>,
+    ) -> Result<Result<McpAccessToken, &'static str>, String> {
+        let now = chrono::Utc::now();
+        let (session_id, client_id) = (session_id.to_string(), client_id.to_string());
+        let code_verifier = code_verifier.map(str::to_string);
+        self.update(move |state| {
+            let Some(session) = state.auth_sessions.get_mut(&session_id) else {
+                return Err("invalid authorization code");
+            };
+            if session.is_expired(now) {
+                return Err("expired authorization code");
             }
-        } else {
-            Err("Session not found".to_string())
+            if let Some(grant_id) = session.grant_id.clone() {
+                // The code may have been intercepted, so its tokens can't be trusted (RFC 6749 4.1.2).
+                warn!("authorization code reused, revoking grant {}", grant_id);
+                revoke_grant(state, &grant_id);
+                return Err("authorization code already used, the grant is revoked");
+            }
+            if session.client_id != client_id {
+                return Err("authorization code issued to another client");
+            }
+            match (&session.code_challenge, code_verifier.as_deref()) {
+                (Some(code_challenge), Some(code_verifier))
+                    if code_challenge.is_verified_by(code_verifier) => {}
+                (Some(_), Some(_)) => return Err("invalid code verifier"),
+                (Some(_), None) => return Err("missing code verifier"),
+                // Also checked on authorization, but the approval form could be forged.
+                (None, _)
+                    if state
+                        .clients
+                        .get(&client_id)
+                        .is_some_and(|client| client.is_public) =>
+                {
+                    return Err("PKCE is required for public clients");
+                }
+                (None, _) => {}
+            }
+            let Some(auth_token) = session.auth_token.clone() else {
+                return Err("no third-party token available for session");
+            };
+
+            let grant = Grant {
+                grant_id: Uuid::new_v4().to_string(),
+                client_id: session.client_id.clone(),
+                scope: session.scope.clone(),
+                auth_token,
+            };
+            session.grant_id = Some(grant.grant_id.clone());
+            Ok(issue_mcp_token(state, grant, now))
+        })
+        .await
+    }
+
+    /// Exchange `refresh_token` for new tokens, the refresh token being rotated.
+    ///
+    /// The outer error is a failure of the store, the inner one a rejection of the grant.
+    async fn refresh_mcp_token(
+        &self,
+        refresh_token: &str,
+        client_id: &str,
+    ) -> Result<Result<McpAccessToken, &'static str>, String> {
+        let now = chrono::Utc::now();
+        let (refresh_token, client_id) = (refresh_token.to_string(), client_id.to_string());
+        self.update(move |state| {
+            let Some(refresh) = state.refresh_tokens.get_mut(&refresh_token) else {
+                return Err("invalid refresh token");
+            };
+            if refresh.is_expired(now) {
+                return Err("expired refresh token");
+            }
+            if refresh.grant.client_id != client_id {
+                return Err("refresh token issued to another client");
+            }
+            if refresh.is_used {
+                // Either the client or an attacker holds a stolen token, and we can't tell which.
+                let grant_id = refresh.grant.grant_id.clone();
+                warn!("refresh token reused, revoking grant {}", grant_id);
+                revoke_grant(state, &grant_id);
+                return Err("refresh token already used, the grant is revoked");
+            }
+
+            refresh.is_used = true;
+            let grant = refresh.grant.clone();
+            Ok(issue_mcp_token(state, grant, now))
+        })
+        .await
+    }
+
+    fn validate_token(&self, token: &str) -> Option<McpAccessToken> {
+        let now = chrono::Utc::now();
+        self.read(|state| {
+            state
+                .access_tokens
+                .get(token)
+                .filter(|token| !token.is_expired(now))
+                .cloned()
+        })
+        .ok()?
+    }
+
+    pub fn is_valid_token(&self, token: &str) -> bool {
+        self.validate_token(token).is_some()
+    }
+
+    /// Periodically remove the expired codes and tokens, until `cancellation_token` is cancelled.
+    pub fn spawn_sweeper(&self, cancellation_token: tokio_util::sync::CancellationToken) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                tokio::select! {
+                    () = cancellation_token.cancelled() => break,
+                    _ = interval.tick() => store.sweep().await,
+                }
+            }
+        });
+    }
+
+    async fn sweep(&self) {
+        let now = chrono::Utc::now();
+        // Avoids rewriting the on-disk store when there is nothing to remove.
+        let has_expired_entries = self.read(|state| {
+            state.auth_sessions.values().any(|s| s.is_expired(now))
+                || state.access_tokens.values().any(|t| t.is_expired(now))
+                || state.refresh_tokens.values().any(|r| r.is_expired(now))
+        });
+        // Errors are logged by `read()` and `update()`.
+        if has_expired_entries != Ok(true) {
+            return;
         }
+
+        let _ = self.update(move |state| {
+            state.auth_sessions.retain(|_, session| !session.is_expired(now));
+            state.access_tokens.retain(|_, token| !token.is_expired(now));
+            state.refresh_tokens.retain(|_, refresh| !refresh.is_expired(now));
+            debug!(
+                "swept expired OAuth entries, {} sessions, {} access tokens and {} refresh tokens left",
+                state.auth_sessions.len(),
+                state.access_tokens.len(),
+                state.refresh_tokens.len()
+            );
+        })
+        .await;
     }
+}
+
+fn update_state<T>(
+    storage: &dyn oauth_storage::OauthStorage,
+    update: impl FnOnce(&mut oauth_storage::OauthState) -> T,
+) -> Result<T, String> {
+    let mut update = Some(update);
+    let mut output = None;
+    storage
+        .update(&mut |state| output = update.take().map(|update| update(state)))
+        .map_err(|error| {
+            error!("Failed to update the OAuth store: {error:#}");
+            format!("Failed to update the OAuth store: {error}")
+        })?;
+
+    output.ok_or_else(|| "The OAuth store didn't apply the update".to_string())
+}
 
-    async fn validate_token(&self, token: &str) -> Option<McpAccessToken> {
-        self.access_tokens.read().await.get(token).cloned()
+fn issue_mcp_token(
+    state: &mut oauth_storage::OauthState,
+    grant: Grant,
+    now: chrono::DateTime<chrono::Utc>,
+) -> McpAccessToken {
+    let access_token = format!("mcp-token-{}", Uuid::new_v4());
+    let refresh_token = format!("mcp-refresh-{}", Uuid::new_v4());
+    let token = McpAccessToken {
+        access_token: access_token.clone(),
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_LIFETIME_SECS,
+        refresh_token: refresh_token.clone(),
+        scope: grant.scope.clone(),
+        auth_token: grant.auth_token.clone(),
+        client_id: grant.client_id.clone(),
+        grant_id: grant.grant_id.clone(),
+        issued_at: now,
+    };
+
+    state.access_tokens.insert(access_token, token.clone());
+    state.refresh_tokens.insert(
+        refresh_token,
+        RefreshToken {
+            grant,
+            is_used: false,
+            issued_at: now,
+        },
+    );
+    token
+}
+
+fn revoke_grant(state: &mut oauth_storage::OauthState, grant_id: &str) {
+    state
+        .refresh_tokens
+        .retain(|_, refresh| refresh.grant.grant_id != grant_id);
+    state
+        .access_tokens
+        .retain(|_, token| token.grant_id != grant_id);
+}
+
+// a registered OAuth client,
+// not used rmcp `OAuthClientConfig` since it can't be persisted
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(super) struct OauthClient {
+    client_id: String,
+    client_secret: Option<String>,
+    scopes: Vec<String>,
+    redirect_uri: String,
+    /// Registered without a secret (`token_endpoint_auth_method` being `none`), so that it must use
+    /// PKCE.
+    #[serde(default)]
+    is_public: bool,
+}
+
+impl OauthClient {
+    fn is_authenticated_by(&self, client_secret: &str) -> bool {
+        let Some(expected_secret) = &self.client_secret else {
+            return true;
+        };
+        // Hashed first, so that the comparison doesn't depend on the secret lengths.
+        let expected_hash = Sha256::digest(expected_secret.as_bytes());
+        let hash = Sha256::digest(client_secret.as_bytes());
+
+        expected_hash.ct_eq(&hash).into()
     }
 }
 
 // a simple session record for auth session
-#[derive(Clone, Debug)]
-struct AuthSession {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(super) struct AuthSession {
     client_id: String,
     scope: Option<String>,
     _state: Option<String>,
-    _created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(alias = "_created_at")]
+    created_at: chrono::DateTime<chrono::Utc>,
     auth_token: Option<AuthToken>,
+    /// Grant of the tokens issued for the authorization code, once redeemed.
+    #[serde(default)]
+    grant_id: Option<String>,
+    #[serde(default)]
+    code_challenge: Option<CodeChallenge>,
+}
+impl AuthSession {
+    fn is_expired(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        now >= self.created_at + chrono::TimeDelta::seconds(AUTH_CODE_LIFETIME_SECS)
+    }
 }
 
 // a simple token record for auth token
@@ -153,8 +427,8 @@ struct AuthToken {
 
 // a simple token record for mcp token ,
 // not used oauth2 token for avoid include oauth2 crate in this example
-#[derive(Clone, Debug, Serialize)]
-struct McpAccessToken {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(super) struct McpAccessToken {
     access_token: String,
     token_type: String,
     expires_in: u64,
@@ -162,6 +436,101 @@ struct McpAccessToken {
     scope: Option<String>,
     auth_token: AuthToken,
     client_id: String,
+    /// Authorization the token comes from, shared by all the tokens refreshed from it.
+    #[serde(default)]
+    grant_id: String,
+    #[serde(default)]
+    issued_at: chrono::DateTime<chrono::Utc>,
+}
+impl McpAccessToken {
+    fn is_expired(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        now >= self.issued_at + chrono::TimeDelta::seconds(self.expires_in as i64)
+    }
+}
+
+// an authorization given by the user, living on through refreshed tokens
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Grant {
+    grant_id: String,
+    client_id: String,
+    scope: Option<String>,
+    auth_token: AuthToken,
+}
+
+// a refresh token record, kept once used to detect its reuse
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(super) struct RefreshToken {
+    grant: Grant,
+    is_used: bool,
+    #[serde(default)]
+    issued_at: chrono::DateTime<chrono::Utc>,
+}
+impl RefreshToken {
+    fn is_expired(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        now >= self.issued_at + chrono::TimeDelta::seconds(REFRESH_TOKEN_LIFETIME_SECS)
+    }
+}
+
+// a PKCE code challenge (RFC 7636), tying the authorization code to the client which asked for it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CodeChallenge {
+    challenge: String,
+    method: CodeChallengeMethod,
+}
+impl CodeChallenge {
+    fn parse(challenge: Option<&str>, method: Option<&str>) -> Result<Option<Self>, &'static str> {
+        let Some(challenge) = challenge else {
+            return match method {
+                Some(_) => Err("code_challenge_method without code_challenge"),
+                None => Ok(None),
+            };
+        };
+        if !is_pkce_string(challenge) {
+            return Err("invalid code_challenge");
+        }
+        let method = match method {
+            Some("S256") => CodeChallengeMethod::S256,
+            // `plain` is the default method (RFC 7636 4.3).
+            Some("plain") | None => CodeChallengeMethod::Plain,
+            Some(_) => return Err("unsupported code_challenge_method"),
+        };
+
+        Ok(Some(Self {
+            challenge: challenge.to_string(),
+            method,
+        }))
+    }
+
+    fn is_verified_by(&self, code_verifier: &str) -> bool {
+        if !is_pkce_string(code_verifier) {
+            return false;
+        }
+        let expected_challenge = match self.method {
+            CodeChallengeMethod::S256 => {
+                BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+            }
+            CodeChallengeMethod::Plain => code_verifier.to_string(),
+        };
+
+        expected_challenge
+            .as_bytes()
+            .ct_eq(self.challenge.as_bytes())
+            .into()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+enum CodeChallengeMethod {
+    S256,
+    Plain,
+}
+
+/// Whether `value` is a valid code verifier, which code challenges are also (RFC 7636 4.1).
+fn is_pkce_string(value: &str) -> bool {
+    (43..=128).contains(&value.len())
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte))
 }
 
 #[derive(Debug, Deserialize)]
@@ -172,6 +541,8 @@ pub struct AuthorizeQuery {
     redirect_uri: String,
     scope: Option<String>,
     state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -191,14 +562,6 @@ struct TokenRequest {
     refresh_token: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct UserInfo {
-    sub: String,
-    name: String,
-    email: String,
-    username: String,
-}
-
 fn generate_random_string(length: usize) -> String {
     rand::rng()
         .sample_iter(&Alphanumeric)
@@ -215,6 +578,8 @@ struct OAuthAuthorizeTemplate {
     scope: String,
     state: String,
     scopes: String,
+    code_challenge: String,
+    code_challenge_method: String,
 }
 
 // Initial OAuth authorize endpoint
@@ -223,10 +588,28 @@ pub async fn handle_get_oauth_authorize(
     State(oauth_store): axum::extract::State<std::sync::Arc<OauthStore>>,
 ) -> impl IntoResponse {
     debug!("doing oauth_authorize");
-    if let Some(_client) = oauth_store
-        .validate_client(&params.client_id, &params.redirect_uri)
-        .await
-    {
+    if let Some(client) = oauth_store.validate_client(&params.client_id, &params.redirect_uri) {
+        let code_challenge = CodeChallenge::parse(
+            params.code_challenge.as_deref(),
+            params.code_challenge_method.as_deref(),
+        );
+        let error_description = match code_challenge {
+            Err(e) => Some(e),
+            Ok(None) if client.is_public => Some("PKCE is required for public clients"),
+            Ok(_) => None,
+        };
+        if let Some(error_description) = error_description {
+            info!("invalid authorization request: {}", error_description);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_request",
+                    "error_description": error_description
+                })),
+            )
+                .into_response();
+        }
+
         let template = OAuthAuthorizeTemplate {
             client_id: params.client_id,
             redirect_uri: params.redirect_uri,
@@ -236,6 +619,8 @@ pub async fn handle_get_oauth_authorize(
                 .scope
                 .clone()
                 .unwrap_or_else(|| "Basic scope".to_string()),
+            code_challenge: params.code_challenge.unwrap_or_default(),
+            code_challenge_method: params.code_challenge_method.unwrap_or_default(),
         };
 
         Html(template.render().unwrap()).into_response()
@@ -259,6 +644,10 @@ pub struct ApprovalForm {
     scope: String,
     state: String,
     approved: String,
+    #[serde(default)]
+    code_challenge: String,
+    #[serde(default)]
+    code_challenge_method: String,
 }
 
 pub async fn handle_post_oauth_approve(
@@ -280,19 +669,51 @@ pub async fn handle_post_oauth_approve(
         return Redirect::to(&redirect_url).into_response();
     }
 
+    // hidden fields of the authorization page, empty when not in the authorization request
+    let code_challenge = match CodeChallenge::parse(
+        Some(form.code_challenge.as_str()).filter(|challenge| !challenge.is_empty()),
+        Some(form.code_challenge_method.as_str()).filter(|method| !method.is_empty()),
+    ) {
+        Ok(code_challenge) => code_challenge,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_request",
+                    "error_description": e
+                })),
+            )
+                .into_response();
+        }
+    };
+
     // user approved the authorization request, generate authorization code
     let session_id = Uuid::new_v4().to_string();
     let auth_code = format!("mcp-code-{}", session_id);
 
     // create new session record authorization information
-    let session_id = oauth_store
+    let session_id = match oauth_store
         .create_auth_session(
             form.client_id.clone(),
             Some(form.scope.clone()),
             Some(form.state.clone()),
+            code_challenge,
             session_id.clone(),
         )
-        .await;
+        .await
+    {
+        Ok(session_id) => session_id,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": "server_error",
+                    "error_description": e
+                })),
+            )
+                .into_response();
+        }
+    };
 
     // create token
     let created_token = AuthToken {
@@ -323,7 +744,11 @@ pub async fn handle_post_oauth_approve(
         }
     );
 
-    info!("authorization approved, redirecting to: {}", redirect_url);
+    // the redirect URL contains the authorization code, which must not end up in the logs
+    info!(
+        "authorization approved for {}, redirecting to: {}",
+        form.client_id, form.redirect_uri
+    );
     Redirect::to(&redirect_url).into_response()
 }
 
@@ -334,6 +759,7 @@ async fn handle_post_oauth_token(
 ) -> impl IntoResponse {
     info!("Received token request");
 
+    let basic_credentials = extract_basic_credentials(request.headers());
     let bytes = match axum::body::to_bytes(request.into_body(), usize::MAX).await {
         Ok(bytes) => bytes,
         Err(e) => {
@@ -349,12 +775,13 @@ async fn handle_post_oauth_token(
         }
     };
 
-    let body_str = String::from_utf8_lossy(&bytes);
-    info!("request body: {}", body_str);
-
-    let token_req = match serde_urlencoded::from_bytes::<TokenRequest>(&bytes) {
+    let mut token_req = match serde_urlencoded::from_bytes::<TokenRequest>(&bytes) {
         Ok(form) => {
-            info!("successfully parsed form data: {:?}", form);
+            // the other fields are credentials (codes, secrets, refresh tokens and verifiers)
+            info!(
+                "successfully parsed form data: grant_type={} client_id={}",
+                form.grant_type, form.client_id
+            );
             form
         }
         Err(e) => {
@@ -369,16 +796,13 @@ async fn handle_post_oauth_token(
                 .into_response();
         }
     };
+    // client_secret_basic authentication, client_secret_post using the form fields
+    if let Some((client_id, client_secret)) = basic_credentials {
+        token_req.client_id = client_id;
+        token_req.client_secret = client_secret;
+    }
     if token_req.grant_type == "refresh_token" {
-        warn!("this easy server only support authorization_code now");
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({
-                "error": "unsupported_grant_type",
-                "error_description": "only authorization_code is supported"
-            })),
-        )
-            .into_response();
+        return handle_refresh_token_grant(&oauth_store, &token_req).await;
     }
     if token_req.grant_type != "authorization_code" {
         info!("unsupported grant type: {}", token_req.grant_type);
@@ -386,7 +810,7 @@ async fn handle_post_oauth_token(
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({
                 "error": "unsupported_grant_type",
-                "error_description": "only authorization_code is supported"
+                "error_description": "only authorization_code and refresh_token are supported"
             })),
         )
             .into_response();
@@ -394,7 +818,7 @@ async fn handle_post_oauth_token(
 
     // get session_id from code
     if !token_req.code.starts_with("mcp-code-") {
-        info!("invalid authorization code: {}", token_req.code);
+        info!("invalid authorization code");
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({
@@ -412,27 +836,32 @@ async fn handle_post_oauth_token(
         token_req.client_id.clone()
     };
 
+    if !oauth_store.authenticate_client(&client_id, &token_req.client_secret) {
+        info!("client authentication failed: {}", client_id);
+        return client_authentication_failed_response();
+    }
+
     // validate client
-    match oauth_store
-        .validate_client(&client_id, &token_req.redirect_uri)
-        .await
-    {
+    match oauth_store.validate_client(&client_id, &token_req.redirect_uri) {
         Some(_) => {
             let session_id = token_req.code.replace("mcp-code-", "");
-            info!("got session id: {}", session_id);
 
             // create mcp access token
-            match oauth_store.create_mcp_token(&session_id).await {
-                Ok(token) => {
+            match oauth_store
+                .create_mcp_token(&session_id, &client_id, token_req.code_verifier.as_deref())
+                .await
+            {
+                Ok(Ok(token)) => {
                     info!("successfully created access token");
+                    token_response(&token)
+                }
+                Ok(Err(e)) => {
+                    info!("refused authorization code: {}", e);
                     (
-                        StatusCode::OK,
+                        StatusCode::BAD_REQUEST,
                         Json(serde_json::json!({
-                            "access_token": token.access_token,
-                            "token_type": token.token_type,
-                            "expires_in": token.expires_in,
-                            "refresh_token": token.refresh_token,
-                            "scope": token.scope,
+                            "error": "invalid_grant",
+                            "error_description": e
                         })),
                     )
                         .into_response()
@@ -467,6 +896,91 @@ async fn handle_post_oauth_token(
     }
 }
 
+// Handle refresh token request from the MCP client
+async fn handle_refresh_token_grant(
+    oauth_store: &OauthStore,
+    token_req: &TokenRequest,
+) -> Response {
+    // handle empty client_id, as for authorization codes
+    let client_id = if token_req.client_id.is_empty() {
+        "mcp-client"
+    } else {
+        token_req.client_id.as_str()
+    };
+
+    if !oauth_store.authenticate_client(client_id, &token_req.client_secret) {
+        info!("client authentication failed: {}", client_id);
+        return client_authentication_failed_response();
+    }
+
+    match oauth_store
+        .refresh_mcp_token(&token_req.refresh_token, client_id)
+        .await
+    {
+        Ok(Ok(token)) => {
+            info!("successfully refreshed access token");
+            token_response(&token)
+        }
+        Ok(Err(e)) => {
+            info!("refused refresh token: {}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_grant",
+                    "error_description": e
+                })),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": "server_error",
+                "error_description": format!("failed to refresh access token: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Client id and secret of an `Authorization: Basic` header (RFC 6749 2.3.1).
+fn extract_basic_credentials(headers: &axum::http::HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(BASE64_STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+fn client_authentication_failed_response() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(serde_json::json!({
+            "error": "invalid_client",
+            "error_description": "client authentication failed"
+        })),
+    )
+        .into_response()
+}
+
+fn token_response(token: &McpAccessToken) -> Response {
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "access_token": token.access_token,
+            "token_type": token.token_type,
+            "expires_in": token.expires_in,
+            "refresh_token": token.refresh_token,
+            "scope": token.scope,
+        })),
+    )
+        .into_response()
+}
+
 pub async fn oauth_middleware(
     State(oauth_store): axum::extract::State<std::sync::Arc<OauthStore>>,
     request: Request<axum::body::Body>,
@@ -480,20 +994,37 @@ pub async fn oauth_middleware(
             if let Some(stripped) = header_str.strip_prefix("Bearer ") {
                 stripped.to_string()
             } else {
-                return StatusCode::UNAUTHORIZED.into_response();
+                return unauthorized_response(false);
             }
         }
         None => {
-            return StatusCode::UNAUTHORIZED.into_response();
+            return unauthorized_response(false);
         }
     };
 
-    match oauth_store.validate_token(&token).await {
+    match oauth_store.validate_token(&token) {
         Some(_) => next.run(request).await,
-        None => StatusCode::UNAUTHORIZED.into_response(),
+        None => unauthorized_response(true),
     }
 }
 
+/// Tell clients presenting a token that it is invalid or expired (RFC 6750), so that they refresh
+/// it rather than give up.
+pub(super) fn unauthorized_response(has_token: bool) -> Response {
+    let challenge = match has_token {
+        true => {
+            r#"Bearer error="invalid_token", error_description="The access token is invalid or expired""#
+        }
+        false => "Bearer",
+    };
+
+    (
+        StatusCode::UNAUTHORIZED,
+        [(axum::http::header::WWW_AUTHENTICATE, challenge)],
+    )
+        .into_response()
+}
+
 pub async fn handle_get_oauth_metadata(
     axum::extract::State(app_state): axum::extract::State<constants::AppState>,
 ) -> impl IntoResponse {
@@ -502,9 +1033,19 @@ pub async fn handle_get_oauth_metadata(
         "response_types_supported".into(),
         Value::Array(vec![Value::String("code".into())]),
     );
+    additional_fields.insert(
+        "grant_types_supported".into(),
+        Value::Array(vec![
+            Value::String("authorization_code".into()),
+            Value::String("refresh_token".into()),
+        ]),
+    );
     additional_fields.insert(
         "code_challenge_methods_supported".into(),
-        Value::Array(vec![Value::String("S256".into())]),
+        Value::Array(vec![
+            Value::String("S256".into()),
+            Value::String("plain".into()),
+        ]),
     );
     let metadata = AuthorizationMetadata {
         authorization_endpoint: format!("https://{}/oauth/authorize", app_state.public_fqdn),
@@ -537,25 +1078,33 @@ async fn handle_post_oauth_register(
     }
 
     let client_id = format!("client-{}", Uuid::new_v4());
-    let client_secret = generate_random_string(32);
+    // public clients (e.g. desktop apps) can't keep a secret
+    let is_public = req.token_endpoint_auth_method == "none";
+    let client_secret = (!is_public).then(|| generate_random_string(32));
 
-    let client = OAuthClientConfig {
+    let client = OauthClient {
         client_id: client_id.clone(),
-        client_secret: Some(client_secret.clone()),
+        client_secret: client_secret.clone(),
         redirect_uri: req.redirect_uris[0].clone(),
         scopes: vec![],
+        is_public,
     };
 
-    oauth_store
-        .clients
-        .write()
-        .await
-        .insert(client_id.clone(), client);
+    if let Err(e) = oauth_store.register_client(client).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": "server_error",
+                "error_description": e
+            })),
+        )
+            .into_response();
+    }
 
     // return client information
     let response = ClientRegistrationResponse {
         client_id,
-        client_secret: Some(client_secret),
+        client_secret,
         client_name: req.client_name,
         redirect_uris: req.redirect_uris,
         additional_fields: HashMap::new(),
@@ -596,3 +1145,147 @@ pub fn oauth_router(app_state: constants::AppState) -> axum::Router {
         .layer(cors_layer)
         .with_state(app_state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Example of RFC 7636 Appendix B.
+    const RFC_CODE_VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const RFC_CODE_CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    #[test]
+    fn verifies_s256_code_challenges() {
+        let challenge = CodeChallenge::parse(Some(RFC_CODE_CHALLENGE), Some("S256"))
+            .unwrap()
+            .unwrap();
+
+        assert!(challenge.is_verified_by(RFC_CODE_VERIFIER));
+        assert!(!challenge.is_verified_by(RFC_CODE_CHALLENGE));
+        assert!(!challenge.is_verified_by(&RFC_CODE_VERIFIER[1..]));
+    }
+
+    #[test]
+    fn verifies_plain_code_challenges() {
+        // `plain` is the default method.
+        for method in [Some("plain"), None] {
+            let challenge = CodeChallenge::parse(Some(RFC_CODE_VERIFIER), method)
+                .unwrap()
+                .unwrap();
+
+            assert!(challenge.is_verified_by(RFC_CODE_VERIFIER));
+            assert!(!challenge.is_verified_by(RFC_CODE_CHALLENGE));
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_code_challenges() {
+        assert!(CodeChallenge::parse(Some(RFC_CODE_CHALLENGE), Some("S512")).is_err());
+        assert!(CodeChallenge::parse(Some("too-short"), Some("S256")).is_err());
+        assert!(CodeChallenge::parse(None, Some("S256")).is_err());
+        assert!(CodeChallenge::parse(None, None).unwrap().is_none());
+    }
+
+    fn oauth_store() -> OauthStore {
+        OauthStore::new(Arc::new(oauth_storage::MemoryOauthStorage::default())).unwrap()
+    }
+
+    /// Issue the first tokens of a new grant to `client_id`.
+    async fn issue_token(store: &OauthStore, client_id: &str) -> McpAccessToken {
+        let grant = Grant {
+            grant_id: Uuid::new_v4().to_string(),
+            client_id: client_id.to_string(),
+            scope: None,
+            auth_token: AuthToken {
+                access_token: "upstream-token".to_string(),
+                token_type: "Bearer".to_string(),
+                expires_in: 3600,
+                refresh_token: "upstream-refresh".to_string(),
+                scope: None,
+            },
+        };
+        store
+            .update(move |state| issue_mcp_token(state, grant, chrono::Utc::now()))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rotates_refresh_tokens() {
+        let store = oauth_store();
+        let token = issue_token(&store, "client").await;
+
+        let refreshed = store
+            .refresh_mcp_token(&token.refresh_token, "client")
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_ne!(refreshed.refresh_token, token.refresh_token);
+        assert_eq!(refreshed.grant_id, token.grant_id);
+        assert!(store.is_valid_token(&refreshed.access_token));
+    }
+
+    #[tokio::test]
+    async fn revokes_the_grant_of_reused_refresh_tokens() {
+        let store = oauth_store();
+        let token = issue_token(&store, "client").await;
+        let other = issue_token(&store, "client").await;
+        let refreshed = store
+            .refresh_mcp_token(&token.refresh_token, "client")
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(
+            store
+                .refresh_mcp_token(&token.refresh_token, "client")
+                .await
+                .unwrap()
+                .is_err()
+        );
+
+        // Every token of the grant is revoked, whether issued before or after the rotation.
+        assert!(!store.is_valid_token(&token.access_token));
+        assert!(!store.is_valid_token(&refreshed.access_token));
+        assert!(
+            store
+                .refresh_mcp_token(&refreshed.refresh_token, "client")
+                .await
+                .unwrap()
+                .is_err()
+        );
+        // Other grants are left alone.
+        assert!(store.is_valid_token(&other.access_token));
+        assert!(
+            store
+                .refresh_mcp_token(&other.refresh_token, "client")
+                .await
+                .unwrap()
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_refresh_tokens_of_other_clients() {
+        let store = oauth_store();
+        let token = issue_token(&store, "client").await;
+
+        assert!(
+            store
+                .refresh_mcp_token(&token.refresh_token, "other-client")
+                .await
+                .unwrap()
+                .is_err()
+        );
+
+        // The refusal doesn't use up the token of its client.
+        assert!(
+            store
+                .refresh_mcp_token(&token.refresh_token, "client")
+                .await
+                .unwrap()
+                .is_ok()
+        );
+    }
+}
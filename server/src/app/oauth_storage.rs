@@ -0,0 +1,413 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use ring::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use super::oauth::{AuthSession, McpAccessToken, OauthClient, RefreshToken};
+
+/// Bumped on incompatible changes of the store file.
+const FILE_VERSION: u32 = 1;
+/// Binds the ciphertext to this use, so that it can't be replayed as another encrypted blob.
+const FILE_AAD: &[u8] = b"workspace-mcp oauth store";
+
+/// Everything the OAuth server remembers.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OauthState {
+    pub(super) clients: HashMap<String, OauthClient>,
+    pub(super) auth_sessions: HashMap<String, AuthSession>,
+    pub(super) access_tokens: HashMap<String, McpAccessToken>,
+    #[serde(default)]
+    pub(super) refresh_tokens: HashMap<String, RefreshToken>,
+}
+
+/// Where [`super::oauth::OauthStore`] keeps its [`OauthState`].
+pub trait OauthStorage: std::fmt::Debug + Send + Sync {
+    /// Call `read` with the current state, which is borrowed since it's read on every MCP request.
+    fn read(&self, read: &mut dyn FnMut(&OauthState)) -> anyhow::Result<()>;
+
+    /// Apply `update` to the current state and save the result, without any other update of the
+    /// same storage in between.
+    fn update(&self, update: &mut dyn FnMut(&mut OauthState)) -> anyhow::Result<()>;
+}
+
+/// State lost on restart, forcing clients to register and authorize again.
+#[derive(Debug, Default)]
+pub struct MemoryOauthStorage {
+    state: Mutex<OauthState>,
+}
+
+impl OauthStorage for MemoryOauthStorage {
+    fn read(&self, read: &mut dyn FnMut(&OauthState)) -> anyhow::Result<()> {
+        read(&self.lock_state());
+
+        Ok(())
+    }
+
+    fn update(&self, update: &mut dyn FnMut(&mut OauthState)) -> anyhow::Result<()> {
+        update(&mut self.lock_state());
+
+        Ok(())
+    }
+}
+
+impl MemoryOauthStorage {
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, OauthState> {
+        // The state is replaced as a whole, so it can't be left half updated by a panic.
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// State kept in an AES-256-GCM encrypted JSON file, surviving restarts.
+///
+/// The key is read from `<path>.key`, generated on first use and only readable by the user. Updates
+/// hold a lock on `<path>.lock`, since several servers (e.g. an old one still shutting down and
+/// the one replacing it) may share the same file. Reads don't, the file being replaced atomically,
+/// and are served from memory until the file changes.
+pub struct FileOauthStorage {
+    path: PathBuf,
+    lock_path: PathBuf,
+    key: LessSafeKey,
+    random: SystemRandom,
+    /// Last state read or written, with the version of the file back then.
+    cache: Mutex<Option<CachedState>>,
+}
+
+struct CachedState {
+    version: Option<FileVersion>,
+    state: OauthState,
+}
+
+/// Identifies a version of the store file. Modification times alone may not change between two
+/// quick writes, but every write replaces the file, giving it another inode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileVersion {
+    modified_at: SystemTime,
+    len: u64,
+    /// Always 0 on platforms without inodes.
+    inode: u64,
+}
+
+impl FileVersion {
+    fn of(metadata: &std::fs::Metadata) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Ok(Self {
+            modified_at: metadata.modified()?,
+            len: metadata.len(),
+            inode,
+        })
+    }
+}
+
+/// On-disk format of [`FileOauthStorage`].
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl FileOauthStorage {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!(
+                    "Cannot create the OAuth store directory `{}`.",
+                    parent.display()
+                )
+            })?;
+        }
+
+        let random = SystemRandom::new();
+        let key = load_or_create_key(&with_extension(path, "key"), &random)?;
+        let storage = Self {
+            path: path.to_path_buf(),
+            lock_path: with_extension(path, "lock"),
+            key,
+            random,
+            cache: Mutex::new(None),
+        };
+        // Fail on startup rather than on the first OAuth request for a corrupted file or wrong key.
+        storage.read(&mut |_| {})?;
+
+        Ok(storage)
+    }
+
+    fn lock(&self) -> anyhow::Result<std::fs::File> {
+        let lock_file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_path)
+            .with_context(|| format!("Cannot open `{}`.", self.lock_path.display()))?;
+        lock_file
+            .lock()
+            .with_context(|| format!("Cannot lock `{}`.", self.lock_path.display()))?;
+
+        // Unlocked when dropped.
+        Ok(lock_file)
+    }
+
+    fn version(&self) -> anyhow::Result<Option<FileVersion>> {
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => Ok(Some(FileVersion::of(&metadata)?)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error)
+                .with_context(|| format!("Cannot read the OAuth store `{}`.", self.path.display())),
+        }
+    }
+
+    /// Reload `cache` if the file changed since it was cached.
+    fn refresh<'a>(
+        &self,
+        cache: &'a mut Option<CachedState>,
+    ) -> anyhow::Result<&'a mut OauthState> {
+        let version = self.version()?;
+        let is_fresh = cache
+            .as_ref()
+            .is_some_and(|cached| cached.version == version);
+        if !is_fresh {
+            let state = match version {
+                Some(_) => self.decrypt(&std::fs::read(&self.path)?).with_context(|| {
+                    format!("Cannot read the OAuth store `{}`.", self.path.display())
+                })?,
+                None => OauthState::default(),
+            };
+            *cache = Some(CachedState { version, state });
+        }
+
+        Ok(&mut cache.as_mut().expect("the cache was just filled").state)
+    }
+
+    /// Must be called with the lock held.
+    fn write(&self, state: &OauthState) -> anyhow::Result<Option<FileVersion>> {
+        let content = self.encrypt(state)?;
+        // Written aside then renamed, so that a crash can't leave a truncated file.
+        let temporary_path = with_extension(&self.path, "tmp");
+        let mut file = create_private_file(&temporary_path, true)?;
+        file.write_all(&content)?;
+        file.sync_all()?;
+        std::fs::rename(&temporary_path, &self.path)
+            .with_context(|| format!("Cannot write the OAuth store `{}`.", self.path.display()))?;
+
+        self.version()
+    }
+
+    fn encrypt(&self, state: &OauthState) -> anyhow::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        self.random
+            .fill(&mut nonce)
+            .map_err(|_| anyhow::anyhow!("Cannot generate a nonce."))?;
+
+        let mut buffer = serde_json::to_vec(state)?;
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(FILE_AAD),
+                &mut buffer,
+            )
+            .map_err(|_| anyhow::anyhow!("Cannot encrypt the OAuth store."))?;
+
+        Ok(serde_json::to_vec_pretty(&EncryptedFile {
+            version: FILE_VERSION,
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(buffer),
+        })?)
+    }
+
+    fn decrypt(&self, content: &[u8]) -> anyhow::Result<OauthState> {
+        let file: EncryptedFile = serde_json::from_slice(content)?;
+        if file.version != FILE_VERSION {
+            anyhow::bail!("Unsupported version {}.", file.version);
+        }
+
+        let nonce = Nonce::try_assume_unique_for_key(&BASE64.decode(file.nonce)?)
+            .map_err(|_| anyhow::anyhow!("Invalid nonce."))?;
+        let mut buffer = BASE64.decode(file.ciphertext)?;
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::from(FILE_AAD), &mut buffer)
+            .map_err(|_| anyhow::anyhow!("Cannot decrypt it, the key may not be the right one."))?;
+
+        Ok(serde_json::from_slice(plaintext)?)
+    }
+
+    fn lock_cache(&self) -> std::sync::MutexGuard<'_, Option<CachedState>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl OauthStorage for FileOauthStorage {
+    fn read(&self, read: &mut dyn FnMut(&OauthState)) -> anyhow::Result<()> {
+        let mut cache = self.lock_cache();
+        read(self.refresh(&mut cache)?);
+
+        Ok(())
+    }
+
+    fn update(&self, update: &mut dyn FnMut(&mut OauthState)) -> anyhow::Result<()> {
+        let _lock = self.lock()?;
+
+        let mut cache = self.lock_cache();
+        let state = self.refresh(&mut cache)?;
+        update(state);
+        match self.write(state) {
+            Ok(version) => {
+                if let Some(cached) = cache.as_mut() {
+                    cached.version = version;
+                }
+                Ok(())
+            }
+            Err(error) => {
+                // The cached state no longer matches the file.
+                *cache = None;
+                Err(error)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for FileOauthStorage {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("FileOauthStorage")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+fn load_or_create_key(path: &Path, random: &SystemRandom) -> anyhow::Result<LessSafeKey> {
+    let key = match std::fs::read_to_string(path) {
+        Ok(content) => BASE64
+            .decode(content.trim())
+            .with_context(|| format!("Invalid OAuth store key `{}`.", path.display()))?,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            let mut key = vec![0; AES_256_GCM.key_len()];
+            random
+                .fill(&mut key)
+                .map_err(|_| anyhow::anyhow!("Cannot generate the OAuth store key."))?;
+            // Fails if another server created the key in the meantime, rather than overwriting it.
+            create_private_file(path, false)?.write_all(BASE64.encode(&key).as_bytes())?;
+            tracing::info!("Generated the OAuth store key `{}`", path.display());
+
+            key
+        }
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Cannot read the OAuth store key `{}`.", path.display()));
+        }
+    };
+
+    let key = UnboundKey::new(&AES_256_GCM, &key)
+        .map_err(|_| anyhow::anyhow!("Invalid OAuth store key `{}`.", path.display()))?;
+
+    Ok(LessSafeKey::new(key))
+}
+
+/// Create a file only readable and writable by the current user (on Unix).
+fn create_private_file(path: &Path, is_overwritten: bool) -> anyhow::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    match is_overwritten {
+        true => options.create(true).truncate(true),
+        false => options.create_new(true),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    options
+        .open(path)
+        .with_context(|| format!("Cannot create `{}`.", path.display()))
+}
+
+/// `path` with `extension` appended to its file name, e.g. `oauth.json.key`.
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(client_id: &str) -> OauthClient {
+        serde_json::from_value(serde_json::json!({
+            "client_id": client_id,
+            "client_secret": null,
+            "scopes": [],
+            "redirect_uri": "http://localhost/callback",
+        }))
+        .unwrap()
+    }
+
+    fn client_ids(storage: &FileOauthStorage) -> Vec<String> {
+        let mut client_ids = Vec::new();
+        storage
+            .read(&mut |state| client_ids = state.clients.keys().cloned().collect())
+            .unwrap();
+        client_ids.sort();
+
+        client_ids
+    }
+
+    #[test]
+    fn sees_the_updates_of_other_storages_of_the_same_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("oauth.json");
+        let storage = FileOauthStorage::open(&path).unwrap();
+        let other_storage = FileOauthStorage::open(&path).unwrap();
+
+        storage
+            .update(&mut |state| {
+                state.clients.insert("a".to_string(), client("a"));
+            })
+            .unwrap();
+        assert_eq!(client_ids(&other_storage), ["a"]);
+
+        // Written within the same tick of the file system clock.
+        let modified_at = std::fs::metadata(&path).unwrap().modified().unwrap();
+        other_storage
+            .update(&mut |state| {
+                state.clients.insert("b".to_string(), client("b"));
+            })
+            .unwrap();
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(modified_at)).unwrap();
+        assert_eq!(client_ids(&storage), ["a", "b"]);
+
+        storage
+            .update(&mut |state| {
+                state.clients.insert("c".to_string(), client("c"));
+            })
+            .unwrap();
+        assert_eq!(client_ids(&other_storage), ["a", "b", "c"]);
+    }
+}
@@ -1,7 +1,8 @@
 #![forbid(unsafe_code)]
 
 use rmcp::transport::{
-    StreamableHttpService, streamable_http_server::session::local::LocalSessionManager,
+    SseServer, StreamableHttpService, sse_server::SseServerConfig,
+    streamable_http_server::session::local::LocalSessionManager,
 };
 
 use super::*;
@@ -13,50 +14,279 @@ async fn index() -> axum::response::Html<&'static str> {
     axum::response::Html(INDEX_HTML)
 }
 
+/// End the response streams of the MCP transports (the `GET` streams of streamable HTTP and the
+/// legacy SSE ones) on shutdown, since they would otherwise keep the graceful shutdown of the
+/// server waiting forever.
+async fn end_streams_on_shutdown(
+    axum::extract::State(shutdown_token): axum::extract::State<tokio_util::sync::CancellationToken>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use tokio_stream::StreamExt;
+
+    next.run(request).await.map(|body| {
+        let mut data_stream = body.into_data_stream();
+        axum::body::Body::from_stream(async_stream::stream! {
+            loop {
+                tokio::select! {
+                    chunk = data_stream.next() => match chunk {
+                        Some(chunk) => yield chunk,
+                        None => break,
+                    },
+                    () = shutdown_token.cancelled() => break,
+                }
+            }
+        })
+    })
+}
+
+pub struct RouterOptions {
+    /// Address the server is actually bound to.
+    pub local_addr: std::net::SocketAddr,
+    /// Public domain of the server (Cloudflare Tunnel), if any.
+    pub public_domain: Option<String>,
+    pub auth_mode: auth::AuthMode,
+    /// Static bearer tokens accepted in [`auth::AuthMode::Bearer`] and [`auth::AuthMode::Both`].
+    pub auth_tokens: Vec<String>,
+    pub workspace_path_as_string: String,
+    pub process_registry: workspace_manager::ProcessRegistry,
+    pub command_policy: std::sync::Arc<workspace_manager::CommandPolicy>,
+    pub approvals: Option<workspace_manager::ApprovalQueue>,
+    pub telemetry: Option<workspace_manager::Telemetry>,
+    /// File persisting the OAuth server state, kept in memory otherwise.
+    pub oauth_store_file: Option<std::path::PathBuf>,
+    /// Cancelled on shutdown to end the MCP response streams and close the legacy SSE sessions.
+    pub shutdown_token: tokio_util::sync::CancellationToken,
+}
+
 pub async fn router(
-    addr: std::net::SocketAddr,
-    _auth_token: String,
-    workspace_path_as_string: String,
+    RouterOptions {
+        local_addr,
+        public_domain,
+        auth_mode,
+        auth_tokens,
+        workspace_path_as_string,
+        process_registry,
+        command_policy,
+        approvals,
+        telemetry,
+        oauth_store_file,
+        shutdown_token,
+    }: RouterOptions,
 ) -> anyhow::Result<axum::Router> {
-    let oauth_store = std::sync::Arc::new(oauth::OauthStore::new());
+    let oauth_storage: std::sync::Arc<dyn oauth_storage::OauthStorage> = match &oauth_store_file {
+        Some(path) => std::sync::Arc::new(oauth_storage::FileOauthStorage::open(path)?),
+        None => std::sync::Arc::new(oauth_storage::MemoryOauthStorage::default()),
+    };
+    let oauth_store = std::sync::Arc::new(oauth::OauthStore::new(oauth_storage)?);
+    oauth_store.spawn_sweeper(shutdown_token.clone());
     let app_state = constants::AppState {
-        local_fqdn: addr.to_string(),
-        public_fqdn: std::env::var("CLOUDFLARED_TUNNEL_DOMAIN")
-            .unwrap_or_else(|_| addr.to_string()),
+        local_fqdn: local_addr.to_string(),
+        public_fqdn: public_domain.unwrap_or_else(|| local_addr.to_string()),
         oauth_store: oauth_store.clone(),
     };
 
-    // let token_store =
-    //     std::sync::Arc::new(simple_oauth::SimpleOauthTokenStore::new(vec![auth_token]));
+    let token_store = std::sync::Arc::new(simple_oauth::SimpleOauthTokenStore::new(auth_tokens));
 
-    let oauth_router = oauth::oauth_router(app_state.clone());
+    let new_workspace_manager = move || {
+        workspace_manager::WorkspaceManager::new(
+            workspace_path_as_string.clone(),
+            process_registry.clone(),
+            command_policy.clone(),
+            approvals.clone(),
+            telemetry.clone(),
+        )
+    };
 
     let mcp_service = StreamableHttpService::new(
-        move || {
-            Ok(workspace_manager::WorkspaceManager::new(
-                workspace_path_as_string.clone(),
-            ))
+        {
+            let new_workspace_manager = new_workspace_manager.clone();
+            move || Ok(new_workspace_manager())
         },
         LocalSessionManager::default().into(),
         Default::default(),
     );
 
+    // Legacy HTTP+SSE transport (2024-11-05) for clients not speaking streamable HTTP yet.
+    let (sse_server, sse_router) = SseServer::new(SseServerConfig {
+        bind: local_addr,
+        sse_path: "/sse".to_string(),
+        post_path: "/message".to_string(),
+        ct: shutdown_token.clone(),
+        sse_keep_alive: None,
+    });
+    sse_server.with_service(new_workspace_manager);
+
     let api_router = axum::Router::new().route("/health", axum::routing::get(|| async { "ok" }));
 
-    let mcp_router = axum::Router::new().nest_service("/mcp", mcp_service);
-    let protected_mcp_router = mcp_router.layer(axum::middleware::from_fn_with_state(
-        app_state.clone(),
-        oauth::oauth_middleware,
-    ));
-    // let protected_mcp_router = mcp_router.layer(axum::middleware::from_fn_with_state(
-    //     token_store.clone(),
-    //     simple_oauth::simple_oauth_middleware,
-    // ));
-
-    Ok(axum::Router::new()
+    let mcp_router = axum::Router::new()
+        .nest_service("/mcp", mcp_service)
+        .merge(sse_router)
+        .layer(axum::middleware::from_fn_with_state(
+            shutdown_token,
+            end_streams_on_shutdown,
+        ));
+    let protected_mcp_router = match auth_mode {
+        auth::AuthMode::Bearer => mcp_router.layer(axum::middleware::from_fn_with_state(
+            token_store,
+            simple_oauth::simple_oauth_middleware,
+        )),
+        auth::AuthMode::Oauth => mcp_router.layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            oauth::oauth_middleware,
+        )),
+        auth::AuthMode::Both => mcp_router.layer(axum::middleware::from_fn_with_state(
+            auth::AuthState {
+                token_store,
+                oauth_store,
+            },
+            auth::bearer_or_oauth_middleware,
+        )),
+    };
+
+    let router = axum::Router::new()
         .route("/", axum::routing::get(index))
         .nest("/api", api_router)
-        .merge(oauth_router)
-        .merge(protected_mcp_router)
-        .with_state(()))
+        .merge(protected_mcp_router);
+    // Clients must not be offered an OAuth flow whose tokens would be refused.
+    let router = match auth_mode {
+        auth::AuthMode::Bearer => router,
+        auth::AuthMode::Oauth | auth::AuthMode::Both => {
+            router.merge(oauth::oauth_router(app_state.clone()))
+        }
+    };
+
+    Ok(router.with_state(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn test_router(auth_mode: auth::AuthMode, directory: &std::path::Path) -> axum::Router {
+        router(RouterOptions {
+            local_addr: std::net::SocketAddr::from(([127, 0, 0, 1], 8080)),
+            public_domain: None,
+            auth_mode,
+            auth_tokens: vec!["SECRET".to_string()],
+            workspace_path_as_string: directory.to_string_lossy().into_owned(),
+            process_registry: workspace_manager::ProcessRegistry::default(),
+            command_policy: Default::default(),
+            approvals: None,
+            telemetry: None,
+            // Shared by the routers of a test, like by servers restarted with another mode.
+            oauth_store_file: Some(directory.join("oauth.json")),
+            shutdown_token: tokio_util::sync::CancellationToken::new(),
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn send(router: &axum::Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .map(|location| location.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (
+            status,
+            location.unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned()),
+        )
+    }
+
+    /// Get an access token as anyone reaching the server could, without any credentials.
+    async fn mint_oauth_token(router: &axum::Router) -> String {
+        let code_verifier = "a".repeat(43);
+        let (_, registration) = send(
+            router,
+            Request::post("/oauth/register")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    r#"{"client_name":"c","redirect_uris":["http://localhost/cb"],"grant_types":["authorization_code"],"response_types":["code"],"token_endpoint_auth_method":"none"}"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+        let registration: serde_json::Value = serde_json::from_str(&registration).unwrap();
+        let client_id = registration["client_id"].as_str().unwrap();
+
+        let (_, location) = send(
+            router,
+            Request::post("/oauth/approve")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(format!(
+                    "client_id={client_id}&redirect_uri=http://localhost/cb&scope=profile&state=s&approved=true&code_challenge={code_verifier}&code_challenge_method=plain"
+                )))
+                .unwrap(),
+        )
+        .await;
+        let code = location
+            .split("code=")
+            .nth(1)
+            .unwrap()
+            .split('&')
+            .next()
+            .unwrap();
+
+        let (status, token) = send(
+            router,
+            Request::post("/oauth/token")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(format!(
+                    "grant_type=authorization_code&code={code}&client_id={client_id}&redirect_uri=http://localhost/cb&code_verifier={code_verifier}"
+                )))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{token}");
+        let token: serde_json::Value = serde_json::from_str(&token).unwrap();
+
+        token["access_token"].as_str().unwrap().to_string()
+    }
+
+    fn initialize_request(token: &str) -> Request<Body> {
+        Request::post("/mcp")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/json, text/event-stream")
+            .body(Body::from(
+                r#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"1"}}}"#,
+            ))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn refuses_oauth_tokens_by_default() {
+        let directory = tempfile::tempdir().unwrap();
+        let oauth_router = test_router(auth::AuthMode::Both, directory.path()).await;
+        let token = mint_oauth_token(&oauth_router).await;
+        let (status, _) = send(&oauth_router, initialize_request(&token)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let router = test_router(auth::AuthMode::default(), directory.path()).await;
+        let (status, _) = send(&router, initialize_request(&token)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(&router, initialize_request("SECRET")).await;
+        assert_eq!(status, StatusCode::OK);
+        // Nor can tokens be minted.
+        let (status, _) = send(
+            &router,
+            Request::post("/oauth/register")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert!(!status.is_success());
+    }
 }
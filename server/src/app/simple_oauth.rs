@@ -1,19 +1,53 @@
 #![forbid(unsafe_code)]
 
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
 pub struct SimpleOauthTokenStore {
-    valid_tokens: Vec<String>,
+    /// SHA-256 of the valid tokens, so that comparisons don't depend on the token lengths.
+    valid_token_hashes: Vec<[u8; 32]>,
 }
 impl SimpleOauthTokenStore {
     pub fn new(valid_tokens: Vec<String>) -> Self {
-        Self { valid_tokens }
+        Self {
+            valid_token_hashes: valid_tokens
+                .iter()
+                .map(|token| Sha256::digest(token.as_bytes()).into())
+                .collect(),
+        }
     }
 
-    fn is_valid(&self, token: &str) -> bool {
-        self.valid_tokens.contains(&token.to_string())
+    /// Check `token` in constant time, comparing it with every valid token.
+    pub fn is_valid(&self, token: &str) -> bool {
+        let token_hash: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+
+        self.valid_token_hashes
+            .iter()
+            .fold(subtle::Choice::from(0), |is_valid, valid_token_hash| {
+                is_valid | valid_token_hash.ct_eq(&token_hash)
+            })
+            .into()
     }
 }
 
-fn extract_token(headers: &axum::http::HeaderMap) -> Option<String> {
+/// Load bearer tokens from a file, one per line, ignoring blank lines and `#` comments.
+pub fn load_tokens_file(path: &std::path::Path) -> anyhow::Result<Vec<String>> {
+    let content = std::fs::read_to_string(path).map_err(|error| {
+        anyhow::anyhow!(
+            "Cannot read auth tokens file `{}`: {error}.",
+            path.display()
+        )
+    })?;
+
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+pub fn extract_token(headers: &axum::http::HeaderMap) -> Option<String> {
     headers
         .get("Authorization")
         .and_then(|value| value.to_str().ok())
@@ -0,0 +1,159 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream, tcp::OwnedWriteHalf},
+    sync::broadcast::error::RecvError,
+};
+
+use super::simple_oauth::SimpleOauthTokenStore;
+use crate::workspace_manager::{Telemetry, TelemetryEvent};
+
+/// How long clients have to authenticate once connected.
+const AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve the IDE telemetry endpoint: a line-delimited JSON-RPC 2.0 stream over TCP, where each tool
+/// call is sent as `tool_call_started` and `tool_call_finished` notifications. Clients must first
+/// send an `authenticate` request with `auth_token` as its `token` param, then may only send
+/// `ping` requests.
+///
+/// The listener must be bound to a loopback address since events contain the tool arguments and
+/// results (file contents, command outputs, etc).
+pub async fn serve_telemetry(listener: TcpListener, telemetry: Telemetry, auth_token: String) {
+    let token_store = Arc::new(SimpleOauthTokenStore::new(vec![auth_token]));
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tracing::debug!("Telemetry client connected from {addr}.");
+                tokio::spawn(handle_connection(
+                    stream,
+                    telemetry.clone(),
+                    token_store.clone(),
+                ));
+            }
+            Err(error) => tracing::warn!("Cannot accept telemetry connection: {error}."),
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    telemetry: Telemetry,
+    token_store: Arc<SimpleOauthTokenStore>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let Ok(Ok(Some(line))) = tokio::time::timeout(AUTHENTICATION_TIMEOUT, lines.next_line()).await
+    else {
+        return;
+    };
+    let (message, is_authenticated) = authentication_response(&line, &token_store);
+    if write_message(&mut writer, &message).await.is_err() || !is_authenticated {
+        tracing::debug!("Telemetry client failed to authenticate.");
+        return;
+    }
+
+    // Subscribed once authenticated, so that no event is sent before.
+    let mut events = telemetry.subscribe();
+
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => notification(&event),
+                Err(RecvError::Lagged(count)) => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "method": "events_dropped",
+                    "params": { "count": count },
+                }),
+                Err(RecvError::Closed) => break,
+            },
+            line = lines.next_line() => match line {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => response(&line),
+                _ => break,
+            },
+        };
+
+        if write_message(&mut writer, &message).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn write_message(
+    writer: &mut OwnedWriteHalf,
+    message: &serde_json::Value,
+) -> std::io::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+
+    writer.write_all(line.as_bytes()).await
+}
+
+fn notification(event: &TelemetryEvent) -> serde_json::Value {
+    let mut notification = serde_json::to_value(event).unwrap_or_default();
+    notification["jsonrpc"] = "2.0".into();
+
+    notification
+}
+
+/// Response to the first request of a client, and whether it authenticated it.
+fn authentication_response(
+    line: &str,
+    token_store: &SimpleOauthTokenStore,
+) -> (serde_json::Value, bool) {
+    let Ok(request) = serde_json::from_str::<serde_json::Value>(line) else {
+        return (parse_error(), false);
+    };
+
+    let id = request.get("id").cloned().unwrap_or_default();
+    let token = request
+        .get("params")
+        .and_then(|params| params.get("token"))
+        .and_then(|token| token.as_str());
+    match (
+        request.get("method").and_then(|method| method.as_str()),
+        token,
+    ) {
+        (Some("authenticate"), Some(token)) if token_store.is_valid(token) => (
+            serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": {} }),
+            true,
+        ),
+        _ => (
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32001,
+                    "message": "Unauthorized: the first request must be `authenticate` with a valid `token`",
+                },
+            }),
+            false,
+        ),
+    }
+}
+
+fn response(line: &str) -> serde_json::Value {
+    let Ok(request) = serde_json::from_str::<serde_json::Value>(line) else {
+        return parse_error();
+    };
+
+    let id = request.get("id").cloned().unwrap_or_default();
+    match request.get("method").and_then(|method| method.as_str()) {
+        Some("ping") => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": {} }),
+        _ => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32601, "message": "Method not found" },
+        }),
+    }
+}
+
+fn parse_error() -> serde_json::Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": { "code": -32700, "message": "Parse error" },
+    })
+}
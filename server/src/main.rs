@@ -8,43 +8,282 @@ use tracing_subscriber::{
     {self},
 };
 
-use mcp_server::app;
+use anyhow::Context;
+use rmcp::ServiceExt;
+
+use mcp_server::{
+    app,
+    workspace_manager::{
+        ApprovalQueue, CommandPolicy, ProcessRegistry, Telemetry, WorkspaceManager,
+    },
+};
 
 /// App configuration from CLI.
 #[derive(Debug, Parser, Clone)]
 struct Args {
-    /// Bearer token required in Authorization header
+    /// How MCP clients connect: over HTTP, or over stdin/stdout for local clients (without auth)
+    #[arg(long, value_enum, default_value = "http")]
+    transport: Transport,
+
+    /// Log file, logs being written to stderr otherwise
+    #[arg(long = "log-file", env = "WORKSPACE_MCP_LOG_FILE")]
+    log_file: Option<std::path::PathBuf>,
+
+    /// Bearer token required in Authorization header (required with the HTTP transport)
     #[arg(long = "auth-token", env = "WORKSPACE_MCP_AUTH_TOKEN")]
-    auth_token: String,
+    auth_token: Option<String>,
+
+    /// File of additional bearer tokens, one per line (blank lines and `#` comments are ignored)
+    #[arg(long = "auth-tokens-file", env = "WORKSPACE_MCP_AUTH_TOKENS_FILE")]
+    auth_tokens_file: Option<std::path::PathBuf>,
+
+    /// How MCP clients authenticate: static bearer tokens, OAuth access tokens (opt-in, since
+    /// anyone reaching the server can authorize a client on its OAuth server) or both
+    #[arg(
+        long = "auth-mode",
+        env = "WORKSPACE_MCP_AUTH_MODE",
+        value_enum,
+        default_value_t
+    )]
+    auth_mode: app::AuthMode,
 
     /// Workspace path
-    #[arg(long = "workspace-path", value_parser)]
+    #[arg(long = "workspace", visible_alias = "workspace-path", value_parser)]
     workspace_path_as_string: String,
 
-    /// MCP Server local port
-    #[arg(long, default_value = "9876")]
-    port: u16,
+    /// MCP Server address, port 0 picking any free port
+    #[arg(long, default_value = "0.0.0.0:9876")]
+    bind: std::net::SocketAddr,
+
+    /// MCP Server port, on all interfaces (shorthand for `--bind 0.0.0.0:<PORT>`)
+    #[arg(long, conflicts_with = "bind")]
+    port: Option<u16>,
+
+    /// Public domain of the MCP Server (Cloudflare Tunnel)
+    #[arg(long = "public-domain", env = "CLOUDFLARED_TUNNEL_DOMAIN")]
+    public_domain: Option<String>,
+
+    /// JSON command policy restricting the commands run by the workspace tools
+    #[arg(long = "command-policy", env = "WORKSPACE_MCP_COMMAND_POLICY")]
+    command_policy_path: Option<std::path::PathBuf>,
+
+    /// Loopback port of the IDE approval channel, enabling approvals of deletions, overwrites and
+    /// commands off the policy allowlist
+    #[arg(long = "approval-port")]
+    approval_port: Option<u16>,
+
+    /// Seconds after which operations waiting for an approval are denied
+    #[arg(long = "approval-timeout-secs", default_value = "120")]
+    approval_timeout_secs: u64,
+
+    /// Workspace relative glob of paths that can be overwritten without approval (repeatable)
+    #[arg(long = "approval-safe-path")]
+    approval_safe_paths: Vec<String>,
+
+    /// Loopback address of the IDE telemetry endpoint, streaming tool calls as line-delimited
+    /// JSON-RPC notifications to clients authenticated with `--auth-token`
+    #[arg(long = "telemetry")]
+    telemetry_bind: Option<std::net::SocketAddr>,
+
+    /// Encrypted file keeping OAuth clients and tokens across restarts, in memory otherwise (its
+    /// key being generated next to it, in `<FILE>.key`)
+    #[arg(long = "oauth-store-file", env = "WORKSPACE_MCP_OAUTH_STORE_FILE")]
+    oauth_store_file: Option<std::path::PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Transport {
+    Http,
+    Stdio,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    // Stdout is reserved to the startup line, or to the MCP messages with the stdio transport.
+    let log_layer = match &args.log_file {
+        Some(path) => {
+            let log_file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Cannot open log file `{}`.", path.display()))?;
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(tracing_subscriber::fmt::writer::BoxMakeWriter::new(
+                    std::sync::Mutex::new(log_file),
+                ))
+        }
+        None => tracing_subscriber::fmt::layer().with_writer(
+            tracing_subscriber::fmt::writer::BoxMakeWriter::new(std::io::stderr),
+        ),
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "debug".to_string().into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(log_layer)
         .init();
 
-    let args = Args::parse();
-    let addr: std::net::SocketAddr = core::net::SocketAddr::from(([0, 0, 0, 0], args.port));
-    let router = app::router(addr, args.auth_token, args.workspace_path_as_string).await?;
+    anyhow::ensure!(
+        std::path::Path::new(&args.workspace_path_as_string).is_dir(),
+        "Workspace path `{}` is not a directory.",
+        args.workspace_path_as_string
+    );
+    let command_policy = match &args.command_policy_path {
+        Some(path) => CommandPolicy::load(path)?,
+        None => CommandPolicy::default(),
+    };
+    let (approvals, approval_addr) = match args.approval_port {
+        Some(approval_port) => {
+            let mut safe_overwrites = globset::GlobSetBuilder::new();
+            for pattern in &args.approval_safe_paths {
+                safe_overwrites.add(
+                    globset::GlobBuilder::new(pattern)
+                        .literal_separator(true)
+                        .build()?,
+                );
+            }
+            let approvals = ApprovalQueue::new(
+                std::time::Duration::from_secs(args.approval_timeout_secs),
+                safe_overwrites.build()?,
+            );
+
+            // Only the IDE, on this machine, must be able to approve operations.
+            let approval_listener = tokio::net::TcpListener::bind(core::net::SocketAddr::from((
+                [127, 0, 0, 1],
+                approval_port,
+            )))
+            .await?;
+            let approval_addr = approval_listener.local_addr()?;
+            tracing::info!("Approval channel listening on http://{}", approval_addr);
+            let auth_token = args.auth_token.clone().context(
+                "`--auth-token` is required to authenticate the IDE on the approval channel.",
+            )?;
+            let approval_router = app::approval_router(approvals.clone(), auth_token);
+            tokio::spawn(async move { axum::serve(approval_listener, approval_router).await });
+
+            (Some(approvals), Some(approval_addr))
+        }
+        None => (None, None),
+    };
+    let (telemetry, telemetry_addr) = match args.telemetry_bind {
+        Some(telemetry_bind) => {
+            // Events contain file contents and command outputs.
+            anyhow::ensure!(
+                telemetry_bind.ip().is_loopback(),
+                "The telemetry address `{telemetry_bind}` must be a loopback address."
+            );
+            let telemetry_listener = tokio::net::TcpListener::bind(telemetry_bind).await?;
+            let telemetry_addr = telemetry_listener.local_addr()?;
+            tracing::info!("Telemetry endpoint listening on tcp://{}", telemetry_addr);
+            let auth_token = args.auth_token.clone().context(
+                "`--auth-token` is required to authenticate the IDE on the telemetry endpoint.",
+            )?;
+            let telemetry = Telemetry::new();
+            tokio::spawn(app::serve_telemetry(
+                telemetry_listener,
+                telemetry.clone(),
+                auth_token,
+            ));
 
-    tracing::info!("Server listening on http://{}", addr);
+            (Some(telemetry), Some(telemetry_addr))
+        }
+        None => (None, None),
+    };
+    let process_registry = ProcessRegistry::default();
+    let command_policy = std::sync::Arc::new(command_policy);
+
+    if args.transport == Transport::Stdio {
+        // Local clients spawn the server themselves, so there is neither a tunnel nor auth.
+        let workspace_manager = WorkspaceManager::new(
+            args.workspace_path_as_string,
+            process_registry.clone(),
+            command_policy,
+            approvals,
+            telemetry,
+        );
+        let service = workspace_manager.serve(rmcp::transport::stdio()).await?;
+        tracing::info!("Server listening on stdio");
+        tokio::select! {
+            _ = service.waiting() => {}
+            () = shutdown_signal() => {}
+        }
+        process_registry.kill_all().await;
+
+        return Ok(());
+    }
+
+    let mut auth_tokens = vec![
+        args.auth_token
+            .clone()
+            .context("`--auth-token` is required with the HTTP transport.")?,
+    ];
+    if let Some(path) = &args.auth_tokens_file {
+        auth_tokens.extend(app::load_tokens_file(path)?);
+    }
+    let addr = match args.port {
+        Some(port) => core::net::SocketAddr::from(([0, 0, 0, 0], port)),
+        None => args.bind,
+    };
     let tcp_listener = tokio::net::TcpListener::bind(addr).await?;
+    let local_addr = tcp_listener.local_addr()?;
+    let shutdown_token = tokio_util::sync::CancellationToken::new();
+    let router = app::router(app::RouterOptions {
+        local_addr,
+        public_domain: args.public_domain.clone(),
+        auth_mode: args.auth_mode,
+        auth_tokens,
+        workspace_path_as_string: args.workspace_path_as_string,
+        process_registry: process_registry.clone(),
+        command_policy,
+        approvals,
+        telemetry,
+        oauth_store_file: args.oauth_store_file.clone(),
+        shutdown_token: shutdown_token.clone(),
+    })
+    .await?;
+
+    tracing::info!("Server listening on http://{}", local_addr);
+    // Machine-readable line telling launchers (e.g. the VS Code extension) the actual addresses,
+    // since ports may have been picked by the system.
+    let local_url = format!("http://{local_addr}");
+    let startup_line = serde_json::json!({
+        "bind": local_addr,
+        "telemetry": telemetry_addr,
+        "approval": approval_addr,
+        "local_url": local_url,
+        "public_url": args
+            .public_domain
+            .map_or(local_url.clone(), |public_domain| format!("https://{public_domain}")),
+    });
+    println!("{startup_line}");
+
     let _ = axum::serve(tcp_listener, router)
-        .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap() })
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            shutdown_token.cancel();
+        })
         .await;
+    // Background processes started by clients must not outlive the server.
+    process_registry.kill_all().await;
 
     Ok(())
 }
+
+/// Wait for Ctrl+C or, on Unix, for SIGTERM (sent by launchers such as the VS Code extension).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("SIGTERM handler should be installable");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::sync::{broadcast, oneshot};
+
+use crate::workspace_manager::error::{WorkspaceError, WorkspaceResult};
+
+/// Number of events kept for IDE listeners lagging behind.
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// Dangerous tool calls waiting for the user to approve them in the IDE.
+///
+/// Tool calls are paused until the IDE resolves their approval, or denied once `timeout` expires.
+#[derive(Clone)]
+pub struct ApprovalQueue {
+    pending: Arc<Mutex<HashMap<String, PendingApproval>>>,
+    events: broadcast::Sender<ApprovalEvent>,
+    timeout: Duration,
+    safe_overwrites: Arc<globset::GlobSet>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalKind {
+    /// Running a program that isn't on the command policy allowlist.
+    Command,
+    Delete,
+    /// Replacing an existing path outside of the safe overwrite set.
+    Overwrite,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ApprovalRequest {
+    pub id: String,
+    pub kind: ApprovalKind,
+    /// Human-readable description of the operation, shown to the user.
+    pub description: String,
+    pub requested_at: String,
+    pub expires_at: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalOutcome {
+    Approved,
+    Denied,
+    TimedOut,
+    /// The tool call was abandoned, e.g. because its session ended.
+    Cancelled,
+}
+
+/// Events streamed to the IDE.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApprovalEvent {
+    Requested {
+        approval: ApprovalRequest,
+    },
+    Resolved {
+        id: String,
+        outcome: ApprovalOutcome,
+    },
+}
+
+struct PendingApproval {
+    request: ApprovalRequest,
+    sender: oneshot::Sender<bool>,
+}
+
+/// Forgets an approval whose tool call was dropped before it got resolved.
+struct PendingGuard<'a> {
+    queue: &'a ApprovalQueue,
+    id: String,
+}
+
+impl ApprovalQueue {
+    /// Create a queue denying approvals after `timeout`, where overwriting the workspace relative
+    /// paths matched by `safe_overwrites` doesn't need an approval.
+    pub fn new(timeout: Duration, safe_overwrites: globset::GlobSet) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        Self {
+            pending: Arc::default(),
+            events,
+            timeout,
+            safe_overwrites: Arc::new(safe_overwrites),
+        }
+    }
+
+    pub fn is_safe_overwrite(&self, relative_path: &std::path::Path) -> bool {
+        self.safe_overwrites.is_match(relative_path)
+    }
+
+    /// Ask the user to approve an operation, waiting for their answer.
+    pub async fn request(&self, kind: ApprovalKind, description: String) -> WorkspaceResult<()> {
+        let now = chrono::Utc::now();
+        let request = ApprovalRequest {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind,
+            description: description.clone(),
+            requested_at: now.to_rfc3339(),
+            expires_at: (now + self.timeout).to_rfc3339(),
+        };
+        let id = request.id.clone();
+        tracing::info!("Waiting for the approval of: {description}");
+
+        let (sender, receiver) = oneshot::channel();
+        self.lock_pending().insert(
+            id.clone(),
+            PendingApproval {
+                request: request.clone(),
+                sender,
+            },
+        );
+        let guard = PendingGuard { queue: self, id };
+        // Nobody may be listening yet: pending approvals can still be listed.
+        let _ = self
+            .events
+            .send(ApprovalEvent::Requested { approval: request });
+
+        let is_approved = match tokio::time::timeout(self.timeout, receiver).await {
+            Ok(is_approved) => is_approved.unwrap_or(false),
+            Err(_) => {
+                self.forget(&guard.id, ApprovalOutcome::TimedOut);
+                return Err(WorkspaceError::NotApproved {
+                    description,
+                    timed_out: true,
+                });
+            }
+        };
+        match is_approved {
+            true => Ok(()),
+            false => Err(WorkspaceError::NotApproved {
+                description,
+                timed_out: false,
+            }),
+        }
+    }
+
+    /// Pending approvals, oldest first.
+    pub fn list(&self) -> Vec<ApprovalRequest> {
+        let mut requests: Vec<ApprovalRequest> = self
+            .lock_pending()
+            .values()
+            .map(|pending| pending.request.clone())
+            .collect();
+        requests.sort_by(|a, b| a.requested_at.cmp(&b.requested_at));
+
+        requests
+    }
+
+    /// Approve or deny a pending approval, returning false when there is no such approval (anymore).
+    pub fn resolve(&self, id: &str, is_approved: bool) -> bool {
+        let Some(pending) = self.lock_pending().remove(id) else {
+            return false;
+        };
+        let _ = pending.sender.send(is_approved);
+        let _ = self.events.send(ApprovalEvent::Resolved {
+            id: id.to_string(),
+            outcome: match is_approved {
+                true => ApprovalOutcome::Approved,
+                false => ApprovalOutcome::Denied,
+            },
+        });
+
+        true
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ApprovalEvent> {
+        self.events.subscribe()
+    }
+
+    fn forget(&self, id: &str, outcome: ApprovalOutcome) {
+        if self.lock_pending().remove(id).is_some() {
+            let _ = self.events.send(ApprovalEvent::Resolved {
+                id: id.to_string(),
+                outcome,
+            });
+        }
+    }
+
+    fn lock_pending(&self) -> MutexGuard<'_, HashMap<String, PendingApproval>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.queue.forget(&self.id, ApprovalOutcome::Cancelled);
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::{Child, Command},
+    sync::mpsc,
+};
+use tokio_util::sync::CancellationToken;
+
+/// How long to keep reading output after the command exited, in case a process that escaped its
+/// process group still holds the pipes open.
+pub const PIPE_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+const READ_CHUNK_SIZE: usize = 8192;
+
+/// Program, arguments, working directory and environment of a command, before it's spawned either
+/// with pipes or in a pseudo-terminal.
+#[derive(Clone, Debug)]
+pub struct CommandSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    pub cwd: PathBuf,
+    /// Variables set on top of the inherited environment.
+    pub env: HashMap<String, String>,
+    /// Inherited variables removed from the environment.
+    pub removed_env: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct CommandOutput {
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the command (Unix only).
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    pub duration: Duration,
+    pub stdout: CapturedOutput,
+    pub stderr: CapturedOutput,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Output captured within a byte budget, keeping both its beginning and its end (where errors and
+/// summaries usually are) and dropping the middle.
+#[derive(Debug)]
+pub struct CapturedOutput {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    limit: usize,
+    total_bytes: usize,
+}
+
+impl CapturedOutput {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            limit,
+            total_bytes: 0,
+        }
+    }
+
+    pub fn push(&mut self, mut chunk: &[u8]) {
+        self.total_bytes += chunk.len();
+
+        let head_limit = self.limit / 2;
+        if self.head.len() < head_limit {
+            let length = (head_limit - self.head.len()).min(chunk.len());
+            self.head.extend_from_slice(&chunk[..length]);
+            chunk = &chunk[length..];
+        }
+
+        self.tail.extend(chunk);
+        let excess = self.tail.len().saturating_sub(self.limit - head_limit);
+        self.tail.drain(..excess);
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.total_bytes > self.limit
+    }
+
+    /// Lossy UTF-8 text of the output, with a marker where bytes were dropped.
+    pub fn to_text(&self) -> String {
+        let tail: Vec<u8> = self.tail.iter().copied().collect();
+        let head = String::from_utf8_lossy(&self.head);
+        let tail = String::from_utf8_lossy(&tail);
+        if !self.is_truncated() {
+            return format!("{head}{tail}");
+        }
+
+        let omitted_bytes = self.total_bytes - self.head.len() - self.tail.len();
+        let separator = if head.is_empty() || head.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        format!("{head}{separator}[truncated: {omitted_bytes} bytes omitted]\n{tail}")
+    }
+}
+
+impl CommandSpec {
+    pub fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).current_dir(&self.cwd);
+        for name in &self.removed_env {
+            command.env_remove(name);
+        }
+        command.envs(&self.env);
+
+        command
+    }
+
+    pub fn to_pty_command(&self) -> portable_pty::CommandBuilder {
+        let mut command = portable_pty::CommandBuilder::new(&self.program);
+        command.args(&self.args);
+        command.cwd(&self.cwd);
+        for name in &self.removed_env {
+            command.env_remove(name);
+        }
+        for (name, value) in &self.env {
+            command.env(name, value);
+        }
+        if command.get_env("TERM").is_none() {
+            command.env("TERM", "xterm-256color");
+        }
+
+        command
+    }
+}
+
+/// Run `command` to completion with a null stdin, killing it (and the processes it spawned) when
+/// `timeout` expires or `cancellation` is cancelled. Each output stream is captured within
+/// `max_output_bytes`, while `on_output` is called with every chunk as soon as it's read.
+pub async fn run(
+    mut command: Command,
+    timeout: Duration,
+    max_output_bytes: usize,
+    cancellation: &CancellationToken,
+    mut on_output: impl FnMut(OutputStream, &[u8]),
+) -> std::io::Result<CommandOutput> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // Run the command in its own process group so that it can be killed along with its children.
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let started_at = Instant::now();
+    let mut child = command.spawn()?;
+    let pid = child.id();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward_output(stdout, OutputStream::Stdout, sender.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward_output(stderr, OutputStream::Stderr, sender));
+    }
+
+    let mut stdout = CapturedOutput::new(max_output_bytes);
+    let mut stderr = CapturedOutput::new(max_output_bytes);
+    let mut capture = |(stream, chunk): (OutputStream, Vec<u8>)| {
+        on_output(stream, &chunk);
+        match stream {
+            OutputStream::Stdout => stdout.push(&chunk),
+            OutputStream::Stderr => stderr.push(&chunk),
+        }
+    };
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+    let mut timed_out = false;
+    let status: ExitStatus = loop {
+        tokio::select! {
+            Some(output) = receiver.recv() => capture(output),
+            status = child.wait() => break status?,
+            () = &mut deadline => {
+                timed_out = true;
+                kill(pid, &mut child);
+                break child.wait().await?;
+            }
+            // The response of a cancelled request is discarded, only the command must be stopped.
+            () = cancellation.cancelled() => {
+                kill(pid, &mut child);
+                break child.wait().await?;
+            }
+        }
+    };
+    let duration = started_at.elapsed();
+
+    // Background processes left behind by the command would otherwise keep the pipes open.
+    kill(pid, &mut child);
+    let _ = tokio::time::timeout(PIPE_DRAIN_TIMEOUT, async {
+        while let Some(output) = receiver.recv().await {
+            capture(output);
+        }
+    })
+    .await;
+
+    Ok(CommandOutput {
+        exit_code: status.code(),
+        signal: exit_signal(&status),
+        timed_out,
+        duration,
+        stdout,
+        stderr,
+    })
+}
+
+pub async fn forward_output(
+    mut reader: impl AsyncRead + Unpin,
+    stream: OutputStream,
+    sender: mpsc::UnboundedSender<(OutputStream, Vec<u8>)>,
+) {
+    let mut buffer = vec![0; READ_CHUNK_SIZE];
+    while let Ok(length) = reader.read(&mut buffer).await
+        && length > 0
+    {
+        if sender.send((stream, buffer[..length].to_vec())).is_err() {
+            break;
+        }
+    }
+}
+
+/// Kill the process group led by `pid`, or only the child process where groups don't exist.
+pub fn kill(pid: Option<u32>, child: &mut Child) {
+    kill_process_group(pid);
+    #[cfg(not(unix))]
+    let _ = child.start_kill();
+    #[cfg(unix)]
+    let _ = child;
+}
+
+#[cfg(unix)]
+pub fn kill_process_group(pid: Option<u32>) {
+    use nix::{sys::signal, unistd::Pid};
+
+    if let Some(pid) = pid.and_then(|pid| i32::try_from(pid).ok()) {
+        // The group may already be gone.
+        let _ = signal::killpg(Pid::from_raw(pid), signal::Signal::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn kill_process_group(_pid: Option<u32>) {}
+
+#[cfg(unix)]
+pub fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+pub fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
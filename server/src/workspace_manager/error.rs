@@ -0,0 +1,224 @@
+use rmcp::{ErrorData, model::ErrorCode};
+
+/// Errors returned by workspace tools.
+///
+/// Paths are always the workspace relative paths sent by the client: resolved host paths must
+/// never leak through errors.
+#[derive(Debug)]
+pub enum WorkspaceError {
+    NotFound {
+        path: String,
+        hint: Option<&'static str>,
+    },
+    AlreadyExists {
+        path: String,
+        hint: Option<&'static str>,
+    },
+    /// The path resolves outside of the workspace root (directly, via `..` or via a symlink).
+    OutsideWorkspace {
+        path: String,
+    },
+    /// The path goes through a symlink whose target doesn't exist.
+    BrokenSymlink {
+        path: String,
+    },
+    PermissionDenied {
+        path: String,
+    },
+    IsADirectory {
+        path: String,
+        hint: Option<&'static str>,
+    },
+    NotADirectory {
+        path: String,
+    },
+    /// The file doesn't match what the client expects (stale view, ambiguous edit, etc).
+    Conflict {
+        path: String,
+        reason: String,
+    },
+    TooLarge {
+        path: String,
+        size: u64,
+        limit: u64,
+    },
+    InvalidArgument {
+        message: String,
+    },
+    /// The command policy forbids running the command, `rule` being the policy field that matched.
+    PolicyDenied {
+        rule: &'static str,
+        reason: String,
+    },
+    /// The session already runs as many background processes as allowed.
+    TooManyProcesses {
+        limit: usize,
+    },
+    /// The user denied the operation in the IDE, or didn't approve it in time.
+    NotApproved {
+        description: String,
+        timed_out: bool,
+    },
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+pub type WorkspaceResult<T> = Result<T, WorkspaceError>;
+
+impl WorkspaceError {
+    /// Map an I/O error occurring on `path` to the closest variant.
+    pub fn from_io(path: &str, source: std::io::Error) -> Self {
+        let path = path.to_string();
+        match source.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound { path, hint: None },
+            std::io::ErrorKind::AlreadyExists => Self::AlreadyExists { path, hint: None },
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied { path },
+            std::io::ErrorKind::IsADirectory => Self::IsADirectory { path, hint: None },
+            std::io::ErrorKind::NotADirectory => Self::NotADirectory { path },
+            _ => Self::Io { path, source },
+        }
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::InvalidArgument {
+            message: message.into(),
+        }
+    }
+
+    /// Machine-readable error kind, exposed as `data.kind`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NotFound { .. } => "not_found",
+            Self::AlreadyExists { .. } => "already_exists",
+            Self::OutsideWorkspace { .. } => "outside_workspace",
+            Self::BrokenSymlink { .. } => "broken_symlink",
+            Self::PermissionDenied { .. } => "permission_denied",
+            Self::IsADirectory { .. } => "is_a_directory",
+            Self::NotADirectory { .. } => "not_a_directory",
+            Self::Conflict { .. } => "conflict",
+            Self::TooLarge { .. } => "too_large",
+            Self::InvalidArgument { .. } => "invalid_argument",
+            Self::PolicyDenied { .. } => "policy_denied",
+            Self::NotApproved { .. } => "not_approved",
+            Self::TooManyProcesses { .. } => "too_many_processes",
+            Self::Io { .. } => "io",
+        }
+    }
+
+    fn code(&self) -> ErrorCode {
+        match self {
+            Self::NotFound { .. } => ErrorCode::RESOURCE_NOT_FOUND,
+            Self::PermissionDenied { .. }
+            | Self::Conflict { .. }
+            | Self::PolicyDenied { .. }
+            | Self::NotApproved { .. }
+            | Self::TooManyProcesses { .. } => ErrorCode::INVALID_REQUEST,
+            Self::Io { .. } => ErrorCode::INTERNAL_ERROR,
+            _ => ErrorCode::INVALID_PARAMS,
+        }
+    }
+
+    fn path(&self) -> Option<&str> {
+        match self {
+            Self::NotFound { path, .. }
+            | Self::AlreadyExists { path, .. }
+            | Self::OutsideWorkspace { path }
+            | Self::BrokenSymlink { path }
+            | Self::PermissionDenied { path }
+            | Self::IsADirectory { path, .. }
+            | Self::NotADirectory { path }
+            | Self::Conflict { path, .. }
+            | Self::TooLarge { path, .. }
+            | Self::Io { path, .. } => Some(path),
+            Self::InvalidArgument { .. }
+            | Self::PolicyDenied { .. }
+            | Self::NotApproved { .. }
+            | Self::TooManyProcesses { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for WorkspaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound { path, hint } => {
+                write!(f, "`{path}` doesn't exist.")?;
+                hint.map_or(Ok(()), |hint| write!(f, " {hint}"))
+            }
+            Self::AlreadyExists { path, hint } => {
+                write!(f, "`{path}` already exists.")?;
+                hint.map_or(Ok(()), |hint| write!(f, " {hint}"))
+            }
+            Self::OutsideWorkspace { path } => write!(f, "`{path}` is outside of the workspace."),
+            Self::BrokenSymlink { path } => write!(f, "`{path}` goes through a broken symlink."),
+            Self::PermissionDenied { path } => write!(f, "Permission denied on `{path}`."),
+            Self::IsADirectory { path, hint } => {
+                write!(f, "`{path}` is a directory.")?;
+                hint.map_or(Ok(()), |hint| write!(f, " {hint}"))
+            }
+            Self::NotADirectory { path } => write!(f, "`{path}` is not a directory."),
+            Self::Conflict { path, reason } => write!(f, "Conflict on `{path}`: {reason}"),
+            Self::TooLarge { path, size, limit } => write!(
+                f,
+                "`{path}` is too large ({size} bytes, the limit is {limit} bytes)."
+            ),
+            Self::InvalidArgument { message } => write!(f, "{message}"),
+            Self::PolicyDenied { rule, reason } => {
+                write!(f, "Denied by the command policy (`{rule}`): {reason}")
+            }
+            Self::NotApproved {
+                description,
+                timed_out,
+            } => match timed_out {
+                true => write!(f, "Not approved in time by the user: {description}."),
+                false => write!(f, "Denied by the user: {description}."),
+            },
+            Self::TooManyProcesses { limit } => write!(
+                f,
+                "The session already runs {limit} background processes, the maximum. Stop some with `kill_process` first."
+            ),
+            Self::Io { path, source } => write!(f, "I/O error on `{path}`: {source}."),
+        }
+    }
+}
+
+impl std::error::Error for WorkspaceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<WorkspaceError> for ErrorData {
+    fn from(error: WorkspaceError) -> Self {
+        match &error {
+            WorkspaceError::Io { source, .. } => tracing::error!("{error} ({source:?})"),
+            WorkspaceError::PolicyDenied { .. } => tracing::warn!("{error}"),
+            _ => tracing::debug!("{error}"),
+        }
+
+        let mut data = serde_json::json!({ "kind": error.kind() });
+        if let Some(path) = error.path() {
+            data["path"] = path.into();
+        }
+        if let WorkspaceError::TooLarge { size, limit, .. } = &error {
+            data["size"] = (*size).into();
+            data["limit"] = (*limit).into();
+        }
+        if let WorkspaceError::PolicyDenied { rule, .. } = &error {
+            data["rule"] = (*rule).into();
+        }
+        if let WorkspaceError::TooManyProcesses { limit } = &error {
+            data["limit"] = (*limit).into();
+        }
+        if let WorkspaceError::NotApproved { timed_out, .. } = &error {
+            data["timed_out"] = (*timed_out).into();
+        }
+
+        ErrorData::new(error.code(), error.to_string(), Some(data))
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+/// Hex-encoded SHA-256 digest of some file content.
+pub fn hash_content(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Write `content` to `path` through a sibling temporary file and a rename, so that readers (and
+/// a crash or a cancelled request) can only ever observe the previous or the new content.
+///
+/// The permissions of an existing file are preserved.
+pub fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let temp_path = write_temp_sibling(path, content)?;
+    if let Ok(metadata) = std::fs::metadata(path)
+        && let Err(error) = std::fs::set_permissions(&temp_path, metadata.permissions())
+    {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(error);
+    }
+
+    std::fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp_path);
+    })
+}
+
+/// Same as [`write_atomically`] but fails with [`std::io::ErrorKind::AlreadyExists`] if `path`
+/// already exists, without any window where a concurrently created file could be clobbered.
+pub fn create_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let temp_path = write_temp_sibling(path, content)?;
+    // Unlike `rename`, `hard_link` refuses to replace an existing destination.
+    let result = std::fs::hard_link(&temp_path, path);
+    let _ = std::fs::remove_file(&temp_path);
+
+    result
+}
+
+/// Move a file or a directory, falling back to a recursive copy followed by a removal when a plain
+/// rename isn't possible (e.g. across devices).
+pub fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_recursively(from, to).inspect_err(|_| {
+                let _ = remove_path(to);
+            })?;
+            remove_path(from)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Copy a file or a directory tree, preserving permissions and timestamps and recreating symlinks
+/// as is.
+pub fn copy_recursively(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        return copy_symlink(from, to);
+    }
+
+    if metadata.is_dir() {
+        std::fs::create_dir(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        std::fs::set_permissions(to, metadata.permissions())?;
+    } else {
+        std::fs::copy(from, to)?;
+    }
+    // Directory timestamps are set last since copying their content updates them.
+    filetime::set_file_times(
+        to,
+        filetime::FileTime::from_last_access_time(&metadata),
+        filetime::FileTime::from_last_modification_time(&metadata),
+    )
+}
+
+/// Remove a file, a symlink or a whole directory tree.
+pub fn remove_path(path: &Path) -> std::io::Result<()> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(windows)]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    let target = std::fs::read_link(from)?;
+    if std::fs::metadata(from).is_ok_and(|metadata| metadata.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+/// A change committed by [`apply_atomically`].
+pub struct FileChange {
+    pub path: PathBuf,
+    /// New content of the file, or `None` to delete it.
+    pub content: Option<Vec<u8>>,
+    /// Permissions given to the written file (e.g. the ones of a renamed file).
+    pub permissions: Option<std::fs::Permissions>,
+}
+
+/// Apply all `changes` or none of them.
+///
+/// New contents are first staged into temporary siblings, then existing files are moved aside
+/// into backups before the staged files are renamed into place. Any failure restores the backups.
+pub fn apply_atomically(changes: &[FileChange]) -> std::io::Result<()> {
+    let mut staged: Vec<(PathBuf, &Path)> = Vec::new();
+    for change in changes {
+        let Some(content) = &change.content else {
+            continue;
+        };
+        let result = change
+            .path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| write_temp_sibling(&change.path, content))
+            .and_then(|temp_path| match &change.permissions {
+                Some(permissions) => std::fs::set_permissions(&temp_path, permissions.clone())
+                    .map(|_| temp_path.clone())
+                    .inspect_err(|_| {
+                        let _ = std::fs::remove_file(&temp_path);
+                    }),
+                None => Ok(temp_path),
+            });
+        match result {
+            Ok(temp_path) => staged.push((temp_path, &change.path)),
+            Err(error) => {
+                remove_all(staged.iter().map(|(temp_path, _)| temp_path.as_path()));
+                return Err(error);
+            }
+        }
+    }
+
+    let mut backups: Vec<(PathBuf, &Path)> = Vec::new();
+    for change in changes {
+        if std::fs::symlink_metadata(&change.path).is_err() {
+            continue;
+        }
+        let result = sibling_path(&change.path, "bak").and_then(|backup_path| {
+            std::fs::rename(&change.path, &backup_path).map(|_| backup_path)
+        });
+        let backup_path = match result {
+            Ok(backup_path) => backup_path,
+            Err(error) => {
+                restore_backups(&backups);
+                remove_all(staged.iter().map(|(temp_path, _)| temp_path.as_path()));
+                return Err(error);
+            }
+        };
+        backups.push((backup_path, &change.path));
+    }
+
+    for (index, (temp_path, path)) in staged.iter().enumerate() {
+        if let Err(error) = std::fs::rename(temp_path, path) {
+            remove_all(staged[..index].iter().map(|(_, path)| *path));
+            remove_all(
+                staged[index..]
+                    .iter()
+                    .map(|(temp_path, _)| temp_path.as_path()),
+            );
+            restore_backups(&backups);
+            return Err(error);
+        }
+    }
+
+    remove_all(backups.iter().map(|(backup_path, _)| backup_path.as_path()));
+
+    Ok(())
+}
+
+fn restore_backups(backups: &[(PathBuf, &Path)]) {
+    for (backup_path, path) in backups.iter().rev() {
+        let _ = std::fs::rename(backup_path, path);
+    }
+}
+
+fn remove_all<'a>(paths: impl Iterator<Item = &'a Path>) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+fn sibling_path(path: &Path, extension: &str) -> std::io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing file name")
+    })?;
+
+    Ok(path.with_file_name(format!(
+        ".{}.{}.{extension}",
+        file_name.to_string_lossy(),
+        uuid::Uuid::new_v4()
+    )))
+}
+
+fn write_temp_sibling(path: &Path, content: &[u8]) -> std::io::Result<PathBuf> {
+    let temp_path = sibling_path(path, "tmp")?;
+    let result = std::fs::File::create_new(&temp_path).and_then(|mut file| {
+        file.write_all(content)?;
+        file.sync_all()
+    });
+    if let Err(error) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(error);
+    }
+
+    Ok(temp_path)
+}
@@ -1,4 +1,20 @@
+mod approval;
+mod command;
+mod error;
+mod file_system;
+mod patch;
+mod policy;
+mod process;
+mod pty;
+mod sandbox;
+mod telemetry;
+mod trash;
 mod types;
+#[allow(clippy::module_inception)]
 mod workspace_manager;
 
+pub use approval::{ApprovalEvent, ApprovalQueue, ApprovalRequest};
+pub use policy::CommandPolicy;
+pub use process::ProcessRegistry;
+pub use telemetry::{Telemetry, TelemetryEvent};
 pub use workspace_manager::WorkspaceManager;
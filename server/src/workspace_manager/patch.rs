@@ -0,0 +1,488 @@
+//! Minimal unified diff parser and applier, supporting the Git extended headers agents commonly
+//! produce (`new file mode`, `deleted file mode`, `rename from`/`rename to`).
+
+/// Maximum number of lines a hunk may be moved away from its header position to find a match.
+const MAX_HUNK_OFFSET: usize = 1000;
+
+#[derive(Debug, Default)]
+pub struct FilePatch {
+    /// `None` when the file is created.
+    pub old_path: Option<String>,
+    /// `None` when the file is deleted.
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Default)]
+pub struct Hunk {
+    /// 1-based line of the hunk in the original file, as stated in its header.
+    pub old_start: usize,
+    pub lines: Vec<HunkLine>,
+    pub old_has_no_final_newline: bool,
+    pub new_has_no_final_newline: bool,
+}
+
+#[derive(Debug)]
+pub enum HunkLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+/// How loosely a hunk had to be matched against the current content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Fuzz {
+    Exact,
+    /// Trailing whitespace ignored.
+    TrailingWhitespace,
+    /// Leading and trailing whitespace ignored.
+    Whitespace,
+}
+
+pub struct AppliedPatch {
+    pub content: String,
+    pub fuzzy_hunks: usize,
+}
+
+pub fn parse(diff: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut current: Option<FilePatch> = None;
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            patches.extend(current.take());
+            let (old_path, new_path) = parse_git_diff_paths(rest);
+            current = Some(FilePatch {
+                old_path,
+                new_path,
+                hunks: Vec::new(),
+            });
+        } else if line.starts_with("--- ")
+            && lines
+                .get(index + 1)
+                .is_some_and(|next| next.starts_with("+++ "))
+        {
+            // A `---` header without a preceding `diff --git` header starts a new file patch.
+            if current.as_ref().is_none_or(|patch| !patch.hunks.is_empty()) {
+                patches.extend(current.take());
+                current = Some(FilePatch::default());
+            }
+            let patch = current.get_or_insert_default();
+            patch.old_path = parse_header_path(&line[4..]);
+            patch.new_path = parse_header_path(&lines[index + 1][4..]);
+            index += 1;
+        } else if line.starts_with("@@") {
+            let patch = current
+                .as_mut()
+                .ok_or_else(|| format!("Hunk at line {} has no file header.", index + 1))?;
+            let (hunk, next_index) = parse_hunk(&lines, index)?;
+            patch.hunks.push(hunk);
+            index = next_index;
+            continue;
+        } else if let Some(patch) = current.as_mut() {
+            if let Some(path) = line.strip_prefix("rename from ") {
+                patch.old_path = Some(path.to_string());
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                patch.new_path = Some(path.to_string());
+            } else if line.starts_with("new file mode ") {
+                patch.old_path = None;
+            } else if line.starts_with("deleted file mode ") {
+                patch.new_path = None;
+            } else if line.starts_with("GIT binary patch") || line.starts_with("Binary files ") {
+                return Err("Binary patches are not supported.".to_string());
+            }
+        }
+
+        index += 1;
+    }
+    patches.extend(current);
+
+    if patches.is_empty() {
+        return Err("The patch doesn't contain any file diff.".to_string());
+    }
+    for patch in &patches {
+        if patch.old_path.is_none() && patch.new_path.is_none() {
+            return Err("A file diff has neither a source nor a destination path.".to_string());
+        }
+    }
+
+    Ok(patches)
+}
+
+/// Apply `hunks` to `content`, locating each hunk at its stated position or, failing that, at the
+/// nearest position where its context matches with the smallest possible [`Fuzz`].
+pub fn apply(content: &str, hunks: &[Hunk]) -> Result<AppliedPatch, String> {
+    let line_ending = match content.split_inclusive('\n').next() {
+        Some(line) if line.ends_with("\r\n") => "\r\n",
+        _ => "\n",
+    };
+    // Lines are stored without their line ending, which is kept aside to be written back as is.
+    let mut lines: Vec<(String, &str)> = content
+        .split_inclusive('\n')
+        .map(|line| match line.strip_suffix("\r\n") {
+            Some(text) => (text.to_string(), "\r\n"),
+            None => match line.strip_suffix('\n') {
+                Some(text) => (text.to_string(), "\n"),
+                None => (line.to_string(), ""),
+            },
+        })
+        .collect();
+
+    let mut fuzzy_hunks = 0;
+    // Minimum index of the next hunk, and shift between header positions and current positions.
+    let mut min_index = 0;
+    let mut shift: isize = 0;
+    for (hunk_index, hunk) in hunks.iter().enumerate() {
+        let old: Vec<&str> = hunk
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Removed(text) => Some(text.as_str()),
+                HunkLine::Added(_) => None,
+            })
+            .collect();
+
+        let stated_index = (hunk.old_start.saturating_sub(1) as isize + shift).max(0) as usize;
+        let (index, fuzz) = find_hunk(&lines, &old, stated_index, min_index).ok_or_else(|| {
+            format!(
+                "Hunk #{} (at line {}) doesn't match the current content.",
+                hunk_index + 1,
+                hunk.old_start
+            )
+        })?;
+        if fuzz != Fuzz::Exact || index != stated_index {
+            fuzzy_hunks += 1;
+        }
+
+        let mut replacement: Vec<(String, &str)> = Vec::new();
+        let mut old_index = index;
+        for line in &hunk.lines {
+            match line {
+                HunkLine::Context(_) => {
+                    replacement.push(lines[old_index].clone());
+                    old_index += 1;
+                }
+                HunkLine::Removed(_) => old_index += 1,
+                HunkLine::Added(text) => replacement.push((text.clone(), line_ending)),
+            }
+        }
+        let inserted = replacement.len();
+        lines.splice(index..index + old.len(), replacement);
+
+        min_index = index + inserted;
+        shift += inserted as isize - old.len() as isize;
+        shift += index as isize - stated_index as isize;
+    }
+
+    if let Some(last_hunk) = hunks.last()
+        && let Some((_, ending)) = lines.last_mut()
+    {
+        if last_hunk.new_has_no_final_newline {
+            *ending = "";
+        } else if ending.is_empty() && last_hunk.old_has_no_final_newline {
+            *ending = line_ending;
+        }
+    }
+
+    Ok(AppliedPatch {
+        content: lines
+            .iter()
+            .flat_map(|(text, ending)| [text.as_str(), ending])
+            .collect(),
+        fuzzy_hunks,
+    })
+}
+
+fn find_hunk(
+    lines: &[(String, &str)],
+    old: &[&str],
+    stated_index: usize,
+    min_index: usize,
+) -> Option<(usize, Fuzz)> {
+    if lines.len() < old.len() {
+        return None;
+    }
+    let max_index = lines.len() - old.len();
+    let stated_index = stated_index.clamp(min_index.min(max_index), max_index);
+
+    for fuzz in [Fuzz::Exact, Fuzz::TrailingWhitespace, Fuzz::Whitespace] {
+        for offset in 0..=MAX_HUNK_OFFSET.min(lines.len()) {
+            let candidates = [
+                stated_index.checked_add(offset),
+                stated_index.checked_sub(offset).filter(|_| offset > 0),
+            ];
+            for index in candidates.into_iter().flatten() {
+                if index < min_index || index > max_index {
+                    continue;
+                }
+                let is_match = old
+                    .iter()
+                    .zip(&lines[index..])
+                    .all(|(expected, (actual, _))| is_line_match(expected, actual, fuzz));
+                if is_match {
+                    return Some((index, fuzz));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn is_line_match(expected: &str, actual: &str, fuzz: Fuzz) -> bool {
+    match fuzz {
+        Fuzz::Exact => expected == actual,
+        Fuzz::TrailingWhitespace => expected.trim_end() == actual.trim_end(),
+        Fuzz::Whitespace => expected.trim() == actual.trim(),
+    }
+}
+
+fn parse_hunk(lines: &[&str], header_index: usize) -> Result<(Hunk, usize), String> {
+    let header = lines[header_index];
+    let (old_start, old_count, new_count) = parse_hunk_header(header).ok_or_else(|| {
+        format!(
+            "Invalid hunk header at line {}: `{header}`.",
+            header_index + 1
+        )
+    })?;
+
+    let mut hunk = Hunk {
+        old_start,
+        ..Default::default()
+    };
+    // Counts are trusted when present but agents often get them wrong, so parsing also stops at
+    // anything that can't be part of a hunk.
+    let mut old_remaining = old_count;
+    let mut new_remaining = new_count;
+    let mut index = header_index + 1;
+    while index < lines.len() {
+        let line = lines[index];
+        if line.starts_with("@@") || line.starts_with("diff --git ") {
+            break;
+        }
+        if line.starts_with("--- ")
+            && lines
+                .get(index + 1)
+                .is_some_and(|next| next.starts_with("+++ "))
+        {
+            break;
+        }
+        if let Some(marker) = line.strip_prefix('\\') {
+            if marker.trim_start().starts_with("No newline") {
+                match hunk.lines.last() {
+                    Some(HunkLine::Added(_)) => hunk.new_has_no_final_newline = true,
+                    Some(HunkLine::Removed(_)) => hunk.old_has_no_final_newline = true,
+                    _ => {
+                        hunk.old_has_no_final_newline = true;
+                        hunk.new_has_no_final_newline = true;
+                    }
+                }
+            }
+            index += 1;
+            continue;
+        }
+        if old_remaining == Some(0) && new_remaining == Some(0) {
+            break;
+        }
+
+        let hunk_line = match line.chars().next() {
+            Some(' ') => HunkLine::Context(line[1..].to_string()),
+            // Editors and agents often strip the single space of empty context lines.
+            None => HunkLine::Context(String::new()),
+            Some('-') => HunkLine::Removed(line[1..].to_string()),
+            Some('+') => HunkLine::Added(line[1..].to_string()),
+            Some(_) => break,
+        };
+        if !matches!(hunk_line, HunkLine::Added(_)) {
+            old_remaining = old_remaining.map(|count| count.saturating_sub(1));
+        }
+        if !matches!(hunk_line, HunkLine::Removed(_)) {
+            new_remaining = new_remaining.map(|count| count.saturating_sub(1));
+        }
+        hunk.lines.push(hunk_line);
+        index += 1;
+    }
+
+    // Trailing empty "context" lines are usually just blank lines separating file diffs.
+    while old_remaining.is_none()
+        && matches!(hunk.lines.last(), Some(HunkLine::Context(text)) if text.is_empty())
+    {
+        hunk.lines.pop();
+    }
+
+    Ok((hunk, index))
+}
+
+/// Parse `@@ -old_start[,old_count] +new_start[,new_count] @@`. Counts are optional and ranges may
+/// be missing altogether (`@@ ... @@`), in which case the hunk is located by its context only.
+fn parse_hunk_header(header: &str) -> Option<(usize, Option<usize>, Option<usize>)> {
+    let ranges = header.strip_prefix("@@")?;
+    let ranges = &ranges[..ranges.find("@@").unwrap_or(ranges.len())];
+    let mut parts = ranges.split_whitespace();
+    let (Some(old_range), Some(new_range)) = (parts.next(), parts.next()) else {
+        return Some((1, None, None));
+    };
+
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = parse_range(old_range.strip_prefix('-')?)?;
+    let (_, new_count) = parse_range(new_range.strip_prefix('+')?)?;
+    // An empty original range (e.g. `-0,0`) is located *after* its start line.
+    let old_start = if old_count == 0 {
+        old_start + 1
+    } else {
+        old_start
+    };
+
+    Some((old_start, Some(old_count), Some(new_count)))
+}
+
+fn parse_git_diff_paths(rest: &str) -> (Option<String>, Option<String>) {
+    match rest.split_once(" b/") {
+        Some((old_path, new_path)) => (
+            Some(old_path.trim_start_matches("a/").to_string()),
+            Some(new_path.to_string()),
+        ),
+        None => (None, None),
+    }
+}
+
+fn parse_header_path(value: &str) -> Option<String> {
+    // Strip the optional timestamp separated by a tab.
+    let path = value.split('\t').next().unwrap_or(value).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path.trim_matches('"');
+
+    Some(
+        path.strip_prefix("a/")
+            .or_else(|| path.strip_prefix("b/"))
+            .unwrap_or(path)
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply the single file patch of `diff` to `content`.
+    fn apply_diff(content: &str, diff: &str) -> Result<AppliedPatch, String> {
+        let patches = parse(diff)?;
+        assert_eq!(patches.len(), 1);
+
+        apply(content, &patches[0].hunks)
+    }
+
+    #[test]
+    fn applies_hunks_at_their_stated_position() {
+        let diff = "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n";
+        let applied = apply_diff("a\nb\nc\n", diff).unwrap();
+
+        assert_eq!(applied.content, "a\nB\nc\n");
+        assert_eq!(applied.fuzzy_hunks, 0);
+    }
+
+    #[test]
+    fn locates_shifted_hunks() {
+        let diff = "--- a/f\n+++ b/f\n@@ -2,2 +2,2 @@\n b\n-c\n+C\n@@ -5,1 +5,1 @@\n-e\n+E\n";
+        let applied = apply_diff("new\nnew\na\nb\nc\nd\ne\n", diff).unwrap();
+
+        assert_eq!(applied.content, "new\nnew\na\nb\nC\nd\nE\n");
+        // The shift found for the first hunk applies to the following ones.
+        assert_eq!(applied.fuzzy_hunks, 1);
+    }
+
+    #[test]
+    fn tolerates_whitespace_differences_in_context() {
+        let diff = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n if x {\n-    y\n+    z\n";
+        let applied = apply_diff("  if x {  \n\ty\n", diff).unwrap();
+
+        // Context lines are kept as they are in the file.
+        assert_eq!(applied.content, "  if x {  \n    z\n");
+        assert_eq!(applied.fuzzy_hunks, 1);
+    }
+
+    #[test]
+    fn refuses_hunks_that_dont_match() {
+        let diff = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-x\n+y\n";
+
+        assert!(apply_diff("a\nb\n", diff).is_err());
+    }
+
+    #[test]
+    fn inserts_empty_original_ranges_after_their_start_line() {
+        let at_start = "--- a/f\n+++ b/f\n@@ -0,0 +1 @@\n+first\n";
+        assert_eq!(apply_diff("a\n", at_start).unwrap().content, "first\na\n");
+
+        let at_end = "--- a/f\n+++ b/f\n@@ -1,0 +2 @@\n+last\n";
+        assert_eq!(apply_diff("a\n", at_end).unwrap().content, "a\nlast\n");
+    }
+
+    #[test]
+    fn handles_missing_final_newlines() {
+        let removed =
+            "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n";
+        assert_eq!(apply_diff("a\nb", removed).unwrap().content, "a\nc\n");
+
+        let added = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n\\ No newline at end of file\n";
+        assert_eq!(apply_diff("a\nb\n", added).unwrap().content, "a\nc");
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let diff = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n";
+
+        assert_eq!(
+            apply_diff("a\r\nb\r\n", diff).unwrap().content,
+            "a\r\nc\r\n"
+        );
+    }
+
+    #[test]
+    fn parses_creations_and_deletions() {
+        let patches = parse(
+            "--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1 @@\n+new\n\
+             diff --git a/old.txt b/old.txt\ndeleted file mode 100644\n\
+             --- a/old.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-old\n",
+        )
+        .unwrap();
+
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0].old_path, None);
+        assert_eq!(patches[0].new_path.as_deref(), Some("new.txt"));
+        assert_eq!(apply("", &patches[0].hunks).unwrap().content, "new\n");
+        assert_eq!(patches[1].old_path.as_deref(), Some("old.txt"));
+        assert_eq!(patches[1].new_path, None);
+        assert_eq!(apply("old\n", &patches[1].hunks).unwrap().content, "");
+    }
+
+    #[test]
+    fn parses_renames() {
+        let patches = parse(
+            "diff --git a/old.txt b/new.txt\nsimilarity index 100%\n\
+             rename from old.txt\nrename to new.txt\n",
+        )
+        .unwrap();
+
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].old_path.as_deref(), Some("old.txt"));
+        assert_eq!(patches[0].new_path.as_deref(), Some("new.txt"));
+        assert!(patches[0].hunks.is_empty());
+    }
+
+    #[test]
+    fn refuses_patches_without_file_diffs() {
+        assert!(parse("just some text\n").is_err());
+        assert!(parse("@@ -1 +1 @@\n-a\n+b\n").is_err());
+    }
+}
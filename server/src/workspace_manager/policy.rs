@@ -0,0 +1,203 @@
+use std::{path::Path, time::Duration};
+
+use serde::Deserialize;
+
+use crate::workspace_manager::{
+    command::CommandSpec,
+    error::{WorkspaceError, WorkspaceResult},
+};
+
+/// Rules evaluated before every command spawned by the workspace tools, loaded from the JSON file
+/// passed to `--command-policy`. The default policy allows everything.
+///
+/// ## Example
+/// ```json
+/// {
+///   "allowed_programs": ["cargo", "git", "npm", "sh"],
+///   "denied_argument_patterns": ["^--force$", "rm\\s+-rf"],
+///   "forbidden_env_vars": ["LD_PRELOAD", "AWS_*"],
+///   "max_runtime_secs": 600,
+///   "allow_network": false
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct CommandPolicy {
+    allowed_programs: Option<Vec<String>>,
+    denied_argument_patterns: Vec<regex::Regex>,
+    forbidden_env_vars: Vec<String>,
+    max_runtime: Option<Duration>,
+    is_network_denied: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandPolicyConfig {
+    /// Programs allowed to run. Names without a path separator match the requested program name,
+    /// which is then looked up in the `PATH` of the server (commands can't set `PATH` when this is
+    /// set), others must match the requested program exactly. Shell commands run `sh` (or `cmd` on
+    /// Windows), which must then be allowed. All programs are allowed when missing.
+    allowed_programs: Option<Vec<String>>,
+    /// Regular expressions denying a command when any of its arguments or its whole command line
+    /// matches.
+    #[serde(default)]
+    denied_argument_patterns: Vec<String>,
+    /// Environment variables commands can't set and don't inherit. A trailing `*` matches any
+    /// suffix.
+    #[serde(default)]
+    forbidden_env_vars: Vec<String>,
+    /// Runtime after which commands, background ones included, are killed.
+    max_runtime_secs: Option<u64>,
+    /// Run commands without network access (Linux only, where commands are run in their own
+    /// network namespace with `unshare`). Other platforms deny all commands when it's false.
+    #[serde(default = "default_allow_network")]
+    allow_network: bool,
+}
+
+fn default_allow_network() -> bool {
+    true
+}
+
+impl CommandPolicy {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|error| {
+            anyhow::anyhow!("Cannot read command policy `{}`: {error}.", path.display())
+        })?;
+        let config: CommandPolicyConfig = serde_json::from_str(&content).map_err(|error| {
+            anyhow::anyhow!("Invalid command policy `{}`: {error}.", path.display())
+        })?;
+
+        let denied_argument_patterns = config
+            .denied_argument_patterns
+            .iter()
+            .map(|pattern| {
+                regex::Regex::new(pattern).map_err(|error| {
+                    anyhow::anyhow!("Invalid denied argument pattern `{pattern}`: {error}.")
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        anyhow::ensure!(
+            config.allow_network || cfg!(target_os = "linux"),
+            "`allow_network: false` is only supported on Linux."
+        );
+
+        Ok(Self {
+            allowed_programs: config.allowed_programs,
+            denied_argument_patterns,
+            forbidden_env_vars: config.forbidden_env_vars,
+            max_runtime: config.max_runtime_secs.map(Duration::from_secs),
+            is_network_denied: !config.allow_network,
+        })
+    }
+
+    pub fn max_runtime(&self) -> Option<Duration> {
+        self.max_runtime
+    }
+
+    /// Check `program` against the allowlist, kept apart from [`Self::apply`] so that the user can
+    /// approve programs off the list.
+    pub fn check_program(&self, program: &str) -> WorkspaceResult<()> {
+        if let Some(allowed_programs) = &self.allowed_programs
+            && !allowed_programs
+                .iter()
+                .any(|allowed_program| is_same_program(allowed_program, program))
+        {
+            return Err(WorkspaceError::PolicyDenied {
+                rule: "allowed_programs",
+                reason: format!("`{program}` is not an allowed program."),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Check `spec` against the other rules and return the command to actually spawn, without
+    /// forbidden environment variables and without network access if required.
+    pub fn apply(&self, spec: CommandSpec) -> WorkspaceResult<CommandSpec> {
+        let command_line = [spec.program.as_str()]
+            .into_iter()
+            .chain(spec.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+        for pattern in &self.denied_argument_patterns {
+            if let Some(arg) = spec
+                .args
+                .iter()
+                .chain([&command_line])
+                .find(|arg| pattern.is_match(arg))
+            {
+                return Err(WorkspaceError::PolicyDenied {
+                    rule: "denied_argument_patterns",
+                    reason: format!("`{arg}` matches the denied pattern `{pattern}`."),
+                });
+            }
+        }
+
+        // Otherwise, an allowed program name could be looked up in a directory of the workspace.
+        if self.allowed_programs.is_some()
+            && let Some(name) = spec.env.keys().find(|name| is_program_lookup_env_var(name))
+        {
+            return Err(WorkspaceError::PolicyDenied {
+                rule: "allowed_programs",
+                reason: format!("`{name}` can't be set when programs are restricted."),
+            });
+        }
+        if let Some(name) = spec.env.keys().find(|name| self.is_forbidden_env_var(name)) {
+            return Err(WorkspaceError::PolicyDenied {
+                rule: "forbidden_env_vars",
+                reason: format!("`{name}` is a forbidden environment variable."),
+            });
+        }
+
+        let mut spec = spec;
+        spec.removed_env = std::env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .filter(|name| self.is_forbidden_env_var(name))
+            .collect();
+
+        if self.is_network_denied {
+            let mut args = vec![
+                "--net".to_string(),
+                "--map-current-user".to_string(),
+                "--".to_string(),
+                spec.program,
+            ];
+            args.append(&mut spec.args);
+            spec.program = "unshare".to_string();
+            spec.args = args;
+        }
+
+        Ok(spec)
+    }
+
+    fn is_forbidden_env_var(&self, name: &str) -> bool {
+        self.forbidden_env_vars
+            .iter()
+            .any(|forbidden| match forbidden.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == forbidden,
+            })
+    }
+}
+
+/// Environment variables used to find the program to run.
+fn is_program_lookup_env_var(name: &str) -> bool {
+    match cfg!(windows) {
+        true => name.eq_ignore_ascii_case("PATH") || name.eq_ignore_ascii_case("PATHEXT"),
+        false => name == "PATH",
+    }
+}
+
+fn is_same_program(allowed_program: &str, program: &str) -> bool {
+    let has_separator = |name: &str| name.contains('/') || name.contains('\\');
+    if has_separator(allowed_program) || has_separator(program) {
+        return allowed_program == program;
+    }
+
+    // Windows programs are usually allowed without their extension.
+    let stem = |name: &str| {
+        Path::new(name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+    };
+    allowed_program == program || (cfg!(windows) && stem(allowed_program) == stem(program))
+}
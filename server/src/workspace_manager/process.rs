@@ -0,0 +1,544 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncWriteExt,
+    process::ChildStdin,
+    sync::{Notify, mpsc, oneshot},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::workspace_manager::{
+    command::{self, CommandSpec, OutputStream},
+    error::{WorkspaceError, WorkspaceResult},
+    pty,
+};
+
+/// Bytes of output retained per background process. Older output is dropped first.
+const MAX_RETAINED_OUTPUT_BYTES: usize = 1024 * 1024;
+/// Maximum number of running processes per session, finished ones beyond it being evicted first.
+const MAX_SESSION_PROCESSES: usize = 64;
+/// How long to wait for a killed process to exit.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for a process to read the input written to it.
+const STDIN_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Background processes started by `start_process`, shared by all sessions of the server.
+///
+/// Each process belongs to the session that started it: it's only visible to that session and
+/// killed when the session ends (see [`SessionProcesses`]) or when the server shuts down.
+#[derive(Clone, Default)]
+pub struct ProcessRegistry {
+    processes: Arc<Mutex<HashMap<String, Arc<ManagedProcess>>>>,
+}
+
+pub struct ManagedProcess {
+    pub id: String,
+    session_id: String,
+    /// Command line, for display purposes.
+    pub command: String,
+    /// Workspace relative working directory.
+    pub cwd: String,
+    pub pid: Option<u32>,
+    /// Whether the process runs in a pseudo-terminal.
+    pub is_pty: bool,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    state: Mutex<ProcessState>,
+    /// Notified when new output is available or when the process exits.
+    changed: Notify,
+    input: tokio::sync::Mutex<Option<ProcessInput>>,
+    kill_signal: CancellationToken,
+}
+
+enum ProcessInput {
+    Pipe(ChildStdin),
+    /// PTY writes are blocking, so they are done by a thread running [`pty::forward_input`].
+    Pty(std::sync::mpsc::Sender<pty::InputChunk>),
+}
+
+#[derive(Default)]
+struct ProcessState {
+    output: OutputLog,
+    exit: Option<ProcessExit>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessExit {
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+pub struct OutputSlice {
+    pub text: String,
+    pub next_cursor: u64,
+    /// Bytes between the requested cursor and the oldest retained output, which were dropped.
+    pub dropped_bytes: u64,
+}
+
+/// Interleaved stdout and stderr, addressed by absolute byte offsets so that clients can poll it
+/// with a cursor.
+#[derive(Default)]
+struct OutputLog {
+    data: VecDeque<u8>,
+    /// Offset of the first retained byte.
+    start: u64,
+}
+
+/// Kills the processes of a session when the last `WorkspaceManager` clone of this session is
+/// dropped.
+pub struct SessionProcesses {
+    pub id: String,
+    registry: ProcessRegistry,
+}
+
+impl ProcessRegistry {
+    /// Spawn a process with piped stdin, stdout and stderr.
+    pub fn spawn(
+        &self,
+        session_id: &str,
+        spec: &CommandSpec,
+        description: String,
+        cwd: String,
+    ) -> std::io::Result<Arc<ManagedProcess>> {
+        let mut command = spec.to_command();
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut child = command.spawn()?;
+        let pid = child.id();
+        let input = child.stdin.take().map(ProcessInput::Pipe);
+        let process = ManagedProcess::new(session_id, description, cwd, pid, false, input);
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(command::forward_output(
+                stdout,
+                OutputStream::Stdout,
+                sender.clone(),
+            ));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(command::forward_output(
+                stderr,
+                OutputStream::Stderr,
+                sender,
+            ));
+        }
+        let collector = collect_output(process.clone(), receiver);
+
+        tokio::spawn({
+            let process = process.clone();
+            async move {
+                let status = tokio::select! {
+                    status = child.wait() => status,
+                    () = process.kill_signal.cancelled() => {
+                        command::kill(pid, &mut child);
+                        child.wait().await
+                    }
+                };
+                // Background processes left behind would otherwise keep the pipes open.
+                command::kill(pid, &mut child);
+
+                let exit = status.map(|status| ProcessExit {
+                    exit_code: status.code(),
+                    signal: command::exit_signal(&status),
+                });
+                process.finish(exit, collector).await;
+            }
+        });
+
+        self.register(process.clone());
+
+        Ok(process)
+    }
+
+    /// Spawn a process in a pseudo-terminal of `size`, merging its stdout and stderr.
+    pub fn spawn_pty(
+        &self,
+        session_id: &str,
+        spec: &CommandSpec,
+        description: String,
+        cwd: String,
+        size: portable_pty::PtySize,
+    ) -> std::io::Result<Arc<ManagedProcess>> {
+        let pair = portable_pty::native_pty_system()
+            .openpty(size)
+            .map_err(std::io::Error::other)?;
+        let mut child = pair
+            .slave
+            .spawn_command(spec.to_pty_command())
+            .map_err(|error| {
+                // Errors other than spawning ones come from looking the program up in the `PATH`.
+                error.downcast::<std::io::Error>().unwrap_or_else(|error| {
+                    std::io::Error::new(std::io::ErrorKind::NotFound, error.to_string())
+                })
+            })?;
+        // The slave must be closed on our side for reads to end when the process exits.
+        drop(pair.slave);
+        let pid = child.process_id();
+        let reader = pair
+            .master
+            .try_clone_reader()
+            .map_err(std::io::Error::other)?;
+        let writer = pair.master.take_writer().map_err(std::io::Error::other)?;
+        let (input_sender, input_receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || pty::forward_input(writer, input_receiver));
+        let process = ManagedProcess::new(
+            session_id,
+            description,
+            cwd,
+            pid,
+            true,
+            Some(ProcessInput::Pty(input_sender)),
+        );
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        std::thread::spawn(move || pty::forward_output(reader, sender));
+        let collector = collect_output(process.clone(), receiver);
+
+        let mut killer = child.clone_killer();
+        let mut wait = tokio::task::spawn_blocking(move || child.wait());
+        tokio::spawn({
+            let process = process.clone();
+            async move {
+                let status = tokio::select! {
+                    status = &mut wait => status,
+                    () = process.kill_signal.cancelled() => {
+                        command::kill_process_group(pid);
+                        let _ = killer.kill();
+                        wait.await
+                    }
+                };
+                command::kill_process_group(pid);
+                // The master is kept open until the end so that the process doesn't get a SIGHUP.
+                drop(pair.master);
+
+                let exit = status
+                    .map_err(std::io::Error::other)
+                    .and_then(|status| status)
+                    .map(|status| pty::process_exit(&status));
+                process.finish(exit, collector).await;
+            }
+        });
+
+        self.register(process.clone());
+
+        Ok(process)
+    }
+
+    pub fn get(&self, session_id: &str, id: &str) -> Option<Arc<ManagedProcess>> {
+        self.lock_processes()
+            .get(id)
+            .filter(|process| process.session_id == session_id)
+            .cloned()
+    }
+
+    /// List the processes of a session, oldest first.
+    pub fn list(&self, session_id: &str) -> Vec<Arc<ManagedProcess>> {
+        let mut processes: Vec<Arc<ManagedProcess>> = self
+            .lock_processes()
+            .values()
+            .filter(|process| process.session_id == session_id)
+            .cloned()
+            .collect();
+        processes.sort_by_key(|process| process.started_at);
+
+        processes
+    }
+
+    /// Kill and forget all the processes of a session.
+    pub fn kill_session(&self, session_id: &str) {
+        self.lock_processes().retain(|_, process| {
+            if process.session_id != session_id {
+                return true;
+            }
+            process.kill();
+            false
+        });
+    }
+
+    /// Fail when `session_id` already runs [`MAX_SESSION_PROCESSES`] processes.
+    pub fn check_capacity(&self, session_id: &str) -> WorkspaceResult<()> {
+        let running_count = self
+            .lock_processes()
+            .values()
+            .filter(|process| process.session_id == session_id && process.exit().is_none())
+            .count();
+        match running_count < MAX_SESSION_PROCESSES {
+            true => Ok(()),
+            false => Err(WorkspaceError::TooManyProcesses {
+                limit: MAX_SESSION_PROCESSES,
+            }),
+        }
+    }
+
+    /// Kill all processes and wait for them to exit, on server shutdown.
+    pub async fn kill_all(&self) {
+        let processes: Vec<Arc<ManagedProcess>> = self
+            .lock_processes()
+            .drain()
+            .map(|(_, process)| process)
+            .collect();
+        for process in &processes {
+            process.kill();
+        }
+        for process in processes {
+            let _ = process.wait(KILL_TIMEOUT).await;
+        }
+    }
+
+    /// Add a process, evicting the oldest finished processes of its session beyond
+    /// [`MAX_SESSION_PROCESSES`].
+    fn register(&self, process: Arc<ManagedProcess>) {
+        let mut processes = self.lock_processes();
+        let session_id = process.session_id.clone();
+        processes.insert(process.id.clone(), process);
+
+        let session_process_count = processes
+            .values()
+            .filter(|process| process.session_id == session_id)
+            .count();
+        let mut finished_session_processes: Vec<Arc<ManagedProcess>> = processes
+            .values()
+            .filter(|process| process.session_id == session_id && process.exit().is_some())
+            .cloned()
+            .collect();
+        finished_session_processes.sort_by_key(|process| process.started_at);
+        for evicted in finished_session_processes
+            .iter()
+            .take(session_process_count.saturating_sub(MAX_SESSION_PROCESSES))
+        {
+            processes.remove(&evicted.id);
+        }
+    }
+
+    fn lock_processes(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<ManagedProcess>>> {
+        // A panic while holding the lock can't leave the map in an inconsistent state.
+        self.processes
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl ManagedProcess {
+    fn new(
+        session_id: &str,
+        description: String,
+        cwd: String,
+        pid: Option<u32>,
+        is_pty: bool,
+        input: Option<ProcessInput>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            id: uuid::Uuid::new_v4().simple().to_string()[..8].to_string(),
+            session_id: session_id.to_string(),
+            command: description,
+            cwd,
+            pid,
+            is_pty,
+            started_at: chrono::Utc::now(),
+            state: Mutex::new(ProcessState::default()),
+            changed: Notify::new(),
+            input: tokio::sync::Mutex::new(input),
+            kill_signal: CancellationToken::new(),
+        })
+    }
+
+    pub fn exit(&self) -> Option<ProcessExit> {
+        self.lock_state().exit
+    }
+
+    /// Read output from `cursor`, waiting up to `wait` for new output when there is none yet and
+    /// the process is still running.
+    pub async fn read_output(&self, cursor: u64, max_bytes: usize, wait: Duration) -> OutputSlice {
+        let changed = self.changed.notified();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+
+        let is_pending = {
+            let state = self.lock_state();
+            state.exit.is_none() && cursor >= state.output.end()
+        };
+        if is_pending {
+            let _ = tokio::time::timeout(wait, changed).await;
+        }
+
+        self.lock_state().output.read(cursor, max_bytes)
+    }
+
+    /// Write `bytes` to the process input, failing with [`std::io::ErrorKind::TimedOut`] if the
+    /// process doesn't read it in time. The rest of it may still be delivered later then.
+    pub async fn write_stdin(&self, bytes: &[u8], is_closing: bool) -> std::io::Result<()> {
+        let mut input = self.input.lock().await;
+        let write = async {
+            match input.as_mut() {
+                Some(ProcessInput::Pipe(stdin)) => {
+                    stdin.write_all(bytes).await?;
+                    stdin.flush().await?;
+                }
+                Some(ProcessInput::Pty(sender)) => {
+                    let mut bytes = bytes.to_vec();
+                    if is_closing {
+                        // A terminal has no end of input, programs get one from a Ctrl+D
+                        // keystroke instead.
+                        bytes.push(pty::END_OF_TRANSMISSION);
+                    }
+                    write_pty(sender, bytes).await?;
+                }
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        "stdin was closed",
+                    ));
+                }
+            }
+
+            Ok(())
+        };
+        let result = tokio::time::timeout(STDIN_WRITE_TIMEOUT, write)
+            .await
+            .unwrap_or_else(|_| {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "the process didn't read its input",
+                ))
+            });
+        if is_closing && result.is_ok() {
+            *input = None;
+        }
+
+        result
+    }
+
+    /// Kill the process and the processes it spawned, without waiting for them to exit.
+    pub fn kill(&self) {
+        self.kill_signal.cancel();
+    }
+
+    /// Wait up to `timeout` for the process to exit.
+    pub async fn wait(&self, timeout: Duration) -> Option<ProcessExit> {
+        let _ = tokio::time::timeout(timeout, async {
+            loop {
+                let changed = self.changed.notified();
+                tokio::pin!(changed);
+                changed.as_mut().enable();
+                if self.exit().is_some() {
+                    break;
+                }
+                changed.await;
+            }
+        })
+        .await;
+
+        self.exit()
+    }
+
+    /// Record the exit of the process once its remaining output was collected.
+    async fn finish(&self, exit: std::io::Result<ProcessExit>, collector: JoinHandle<()>) {
+        let _ = tokio::time::timeout(command::PIPE_DRAIN_TIMEOUT, collector).await;
+
+        let exit = exit.unwrap_or_else(|error| {
+            tracing::error!("Cannot wait for process `{}`: {error:?}.", self.id);
+            ProcessExit {
+                exit_code: None,
+                signal: None,
+            }
+        });
+        self.lock_state().exit = Some(exit);
+        self.changed.notify_waiters();
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, ProcessState> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+fn collect_output(
+    process: Arc<ManagedProcess>,
+    mut receiver: mpsc::UnboundedReceiver<(OutputStream, Vec<u8>)>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some((_, chunk)) = receiver.recv().await {
+            process.lock_state().output.push(&chunk);
+            process.changed.notify_waiters();
+        }
+    })
+}
+
+/// Hand `bytes` to the terminal writer thread and wait for it to write them.
+async fn write_pty(
+    sender: &std::sync::mpsc::Sender<pty::InputChunk>,
+    bytes: Vec<u8>,
+) -> std::io::Result<()> {
+    let broken_pipe =
+        || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "the terminal was closed");
+    let (done, written) = oneshot::channel();
+    sender.send((bytes, done)).map_err(|_| broken_pipe())?;
+
+    written.await.map_err(|_| broken_pipe())?
+}
+
+impl OutputLog {
+    fn push(&mut self, chunk: &[u8]) {
+        self.data.extend(chunk);
+        let excess = self.data.len().saturating_sub(MAX_RETAINED_OUTPUT_BYTES);
+        self.data.drain(..excess);
+        self.start += excess as u64;
+    }
+
+    fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+
+    fn read(&self, cursor: u64, max_bytes: usize) -> OutputSlice {
+        let from = cursor.clamp(self.start, self.end());
+        let offset = (from - self.start) as usize;
+        let length = max_bytes.min(self.data.len() - offset);
+        let mut bytes: Vec<u8> = self.data.range(offset..offset + length).copied().collect();
+
+        // Leave a multi-byte character cut by `max_bytes` or by a pending write for the next read.
+        if let Err(error) = std::str::from_utf8(&bytes)
+            && error.error_len().is_none()
+            && error.valid_up_to() > 0
+        {
+            bytes.truncate(error.valid_up_to());
+        }
+
+        OutputSlice {
+            text: String::from_utf8_lossy(&bytes).into_owned(),
+            next_cursor: from + bytes.len() as u64,
+            dropped_bytes: from.saturating_sub(cursor),
+        }
+    }
+}
+
+impl SessionProcesses {
+    pub fn new(registry: ProcessRegistry) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            registry,
+        }
+    }
+
+    pub fn registry(&self) -> &ProcessRegistry {
+        &self.registry
+    }
+}
+
+impl Drop for SessionProcesses {
+    fn drop(&mut self) {
+        self.registry.kill_session(&self.id);
+    }
+}
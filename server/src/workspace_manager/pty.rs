@@ -0,0 +1,111 @@
+use std::io::{Read, Write};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::workspace_manager::{command::OutputStream, process::ProcessExit};
+
+pub const DEFAULT_ROWS: u16 = 24;
+pub const DEFAULT_COLS: u16 = 80;
+/// Ctrl+D, which makes the terminal signal the end of input to the program.
+pub const END_OF_TRANSMISSION: u8 = 0x04;
+
+const READ_CHUNK_SIZE: usize = 8192;
+
+/// Forward the terminal output to `sender` until the terminal is closed. It's blocking, so it must
+/// run in its own thread.
+pub fn forward_output(
+    mut reader: Box<dyn Read + Send>,
+    sender: mpsc::UnboundedSender<(OutputStream, Vec<u8>)>,
+) {
+    let mut buffer = vec![0; READ_CHUNK_SIZE];
+    // Reads fail with `EIO` rather than returning 0 once the process exited on Linux.
+    while let Ok(length) = reader.read(&mut buffer)
+        && length > 0
+    {
+        if sender
+            .send((OutputStream::Stdout, buffer[..length].to_vec()))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Bytes to write to the terminal, with where to report the outcome of the write.
+pub type InputChunk = (Vec<u8>, oneshot::Sender<std::io::Result<()>>);
+
+/// Write the chunks received from `receiver` to the terminal until the channel is closed. Writes
+/// block while the program doesn't read its input and the terminal buffer is full, so it must run
+/// in its own thread.
+pub fn forward_input(
+    mut writer: Box<dyn Write + Send>,
+    receiver: std::sync::mpsc::Receiver<InputChunk>,
+) {
+    for (bytes, done) in receiver {
+        let result = writer.write_all(&bytes).and_then(|()| writer.flush());
+        let is_failed = result.is_err();
+        let _ = done.send(result);
+        if is_failed {
+            break;
+        }
+    }
+}
+
+pub fn process_exit(status: &portable_pty::ExitStatus) -> ProcessExit {
+    match status.signal() {
+        // `portable_pty` only provides the signal description (from `strsignal()`).
+        Some(signal) => ProcessExit {
+            exit_code: None,
+            signal: match signal {
+                "Hangup" => Some(1),
+                "Interrupt" => Some(2),
+                "Quit" => Some(3),
+                "Aborted" => Some(6),
+                "Killed" => Some(9),
+                "Segmentation fault" => Some(11),
+                "Terminated" => Some(15),
+                _ => None,
+            },
+        },
+        None => ProcessExit {
+            exit_code: i32::try_from(status.exit_code()).ok(),
+            signal: None,
+        },
+    }
+}
+
+/// Bytes sent by a terminal for a named key, e.g. `enter`, `up` or `ctrl+c`.
+pub fn key_sequence(name: &str) -> Option<Vec<u8>> {
+    let name = name.to_ascii_lowercase();
+    if let Some(letter) = name.strip_prefix("ctrl+")
+        && let [letter] = letter.as_bytes()
+        && letter.is_ascii_lowercase()
+    {
+        return Some(vec![letter & 0x1f]);
+    }
+
+    let sequence: &[u8] = match name.as_str() {
+        "enter" => b"\r",
+        "tab" => b"\t",
+        "escape" => b"\x1b",
+        "backspace" => b"\x7f",
+        "space" => b" ",
+        "up" => b"\x1b[A",
+        "down" => b"\x1b[B",
+        "right" => b"\x1b[C",
+        "left" => b"\x1b[D",
+        "home" => b"\x1b[H",
+        "end" => b"\x1b[F",
+        "delete" => b"\x1b[3~",
+        "page_up" => b"\x1b[5~",
+        "page_down" => b"\x1b[6~",
+        _ => return None,
+    };
+
+    Some(sequence.to_vec())
+}
+
+/// Remove ANSI escape sequences (colors, cursor moves, etc) and carriage returns.
+pub fn strip_ansi(text: &str) -> String {
+    strip_ansi_escapes::strip_str(text).replace('\r', "")
+}
@@ -0,0 +1,210 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::workspace_manager::error::{WorkspaceError, WorkspaceResult};
+
+/// Resolves workspace relative paths to host paths, guaranteeing that they stay within the
+/// workspace root, including through `..` components and symlinks.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    /// Canonical workspace root.
+    root: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new(workspace_path: &Path) -> Self {
+        let root =
+            dunce::canonicalize(workspace_path).unwrap_or_else(|_| workspace_path.to_path_buf());
+
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolve `path`, following symlinks, to the host path of the file it designates.
+    pub fn resolve(&self, path: &str) -> WorkspaceResult<PathBuf> {
+        self.resolve_with(path, true)
+    }
+
+    /// Resolve `path` like [`Sandbox::resolve`] but without following its last component when it's
+    /// a symlink, for operations acting on the directory entry itself (delete, move, etc).
+    pub fn resolve_entry(&self, path: &str) -> WorkspaceResult<PathBuf> {
+        self.resolve_with(path, false)
+    }
+
+    /// Convert a host path within the workspace back to a workspace relative path.
+    pub fn relativize(&self, full_path: &Path) -> String {
+        full_path
+            .strip_prefix(&self.root)
+            .unwrap_or(full_path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    fn resolve_with(&self, path: &str, is_last_symlink_followed: bool) -> WorkspaceResult<PathBuf> {
+        let outside_workspace = || WorkspaceError::OutsideWorkspace {
+            path: path.to_string(),
+        };
+
+        let requested = Path::new(path);
+        // Absolute paths are accepted as long as they point within the workspace (e.g. when copied
+        // from a compiler output).
+        let relative = match requested.is_absolute() {
+            true => dunce::simplified(requested)
+                .strip_prefix(&self.root)
+                .map_err(|_| outside_workspace())?,
+            false => requested,
+        };
+
+        // Components are resolved one by one against the real file system so that `..` applies to
+        // symlink targets as the OS would, and so that every symlink is checked.
+        let mut resolved = self.root.clone();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !resolved.pop() {
+                        return Err(outside_workspace());
+                    }
+                }
+                Component::Normal(name) => {
+                    let candidate = resolved.join(name);
+                    let is_last = components.peek().is_none();
+                    let is_symlink = std::fs::symlink_metadata(&candidate)
+                        .is_ok_and(|metadata| metadata.file_type().is_symlink());
+                    resolved = match is_symlink && (is_last_symlink_followed || !is_last) {
+                        true => dunce::canonicalize(&candidate).map_err(|_| {
+                            WorkspaceError::BrokenSymlink {
+                                path: path.to_string(),
+                            }
+                        })?,
+                        false => candidate,
+                    };
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(outside_workspace()),
+            }
+        }
+
+        if !resolved.starts_with(&self.root) {
+            return Err(outside_workspace());
+        }
+
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A workspace within a temporary directory, next to an `outside` directory.
+    fn sandbox() -> (tempfile::TempDir, Sandbox) {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::create_dir(directory.path().join("workspace")).unwrap();
+        std::fs::create_dir(directory.path().join("outside")).unwrap();
+        let sandbox = Sandbox::new(&directory.path().join("workspace"));
+
+        (directory, sandbox)
+    }
+
+    #[test]
+    fn resolves_parent_components_within_the_workspace() {
+        let (_directory, sandbox) = sandbox();
+
+        assert_eq!(
+            sandbox.resolve("a/./b/../c").unwrap(),
+            sandbox.root().join("a/c")
+        );
+        assert!(matches!(
+            sandbox.resolve("../outside/file"),
+            Err(WorkspaceError::OutsideWorkspace { .. })
+        ));
+        assert!(matches!(
+            sandbox.resolve("a/../../outside/file"),
+            Err(WorkspaceError::OutsideWorkspace { .. })
+        ));
+        // Only the resolved path matters.
+        assert_eq!(
+            sandbox.resolve("../workspace/file").unwrap(),
+            sandbox.root().join("file")
+        );
+    }
+
+    #[test]
+    fn accepts_absolute_paths_only_within_the_workspace() {
+        let (directory, sandbox) = sandbox();
+
+        let inside = sandbox.root().join("a/b");
+        assert_eq!(sandbox.resolve(inside.to_str().unwrap()).unwrap(), inside);
+        let outside = directory.path().join("outside/file");
+        assert!(matches!(
+            sandbox.resolve(outside.to_str().unwrap()),
+            Err(WorkspaceError::OutsideWorkspace { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_escaping_the_workspace() {
+        let (directory, sandbox) = sandbox();
+        std::os::unix::fs::symlink(
+            directory.path().join("outside"),
+            sandbox.root().join("link"),
+        )
+        .unwrap();
+
+        // A symlink in the middle of the path is always followed.
+        for resolved in [
+            sandbox.resolve("link/file"),
+            sandbox.resolve_entry("link/file"),
+        ] {
+            assert!(matches!(
+                resolved,
+                Err(WorkspaceError::OutsideWorkspace { .. })
+            ));
+        }
+        assert!(matches!(
+            sandbox.resolve("link"),
+            Err(WorkspaceError::OutsideWorkspace { .. })
+        ));
+        assert_eq!(
+            sandbox.resolve_entry("link").unwrap(),
+            sandbox.root().join("link")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_within_the_workspace() {
+        let (_directory, sandbox) = sandbox();
+        std::fs::create_dir(sandbox.root().join("target")).unwrap();
+        std::os::unix::fs::symlink("target", sandbox.root().join("link")).unwrap();
+
+        assert_eq!(
+            sandbox.resolve("link/file").unwrap(),
+            sandbox.root().join("target/file")
+        );
+        assert_eq!(
+            sandbox.resolve("link/../file").unwrap(),
+            sandbox.root().join("file")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_dangling_symlinks_only_as_entries() {
+        let (_directory, sandbox) = sandbox();
+        std::os::unix::fs::symlink("missing", sandbox.root().join("dangling")).unwrap();
+
+        assert!(matches!(
+            sandbox.resolve("dangling"),
+            Err(WorkspaceError::BrokenSymlink { .. })
+        ));
+        assert_eq!(
+            sandbox.resolve_entry("dangling").unwrap(),
+            sandbox.root().join("dangling")
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use rmcp::{ErrorData, model::JsonObject};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Events buffered per telemetry client before the slowest ones get an `events_dropped`
+/// notification instead. Larger than for approvals, since every tool call sends two events.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Broadcasts the tool calls of every session to the IDE listeners of the telemetry endpoint.
+#[derive(Clone)]
+pub struct Telemetry {
+    events: broadcast::Sender<TelemetryEvent>,
+}
+
+/// Tool call events, serialized as the `method` and `params` of JSON-RPC notifications.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum TelemetryEvent {
+    ToolCallStarted {
+        /// JSON-RPC id of the `tools/call` request, only unique within its session.
+        call_id: String,
+        session_id: String,
+        tool: String,
+        arguments: Option<JsonObject>,
+        started_at: String,
+    },
+    ToolCallFinished {
+        call_id: String,
+        session_id: String,
+        tool: String,
+        duration_ms: u64,
+        /// Structured result of the tool, or its whole result when it has none.
+        result: Option<serde_json::Value>,
+        error: Option<ErrorData>,
+    },
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        Self { events }
+    }
+
+    pub fn send(&self, event: TelemetryEvent) {
+        // There may be no listener.
+        let _ = self.events.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TelemetryEvent> {
+        self.events.subscribe()
+    }
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::workspace_manager::file_system;
+
+/// Maximum number of deleted items kept per workspace. Oldest ones are purged first.
+const MAX_TRASH_ENTRIES: usize = 100;
+/// Deleted items older than this are purged.
+const TRASH_RETENTION: chrono::TimeDelta = chrono::TimeDelta::days(7);
+
+const ENTRY_METADATA_FILE_NAME: &str = "entry.json";
+const ENTRY_ITEM_FILE_NAME: &str = "item";
+
+/// Server-managed trash where deleted workspace items are moved instead of being unlinked.
+///
+/// It lives outside of the workspace (in the local data directory of the user) so that deleted
+/// items can't be listed, read or run by tools while still being recoverable.
+#[derive(Clone, Debug)]
+pub struct Trash {
+    root: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TrashEntry {
+    pub id: String,
+    /// Workspace relative path the item was deleted from.
+    pub original_path: String,
+    pub is_directory: bool,
+    /// RFC 3339 deletion date.
+    pub deleted_at: String,
+}
+
+impl Trash {
+    pub fn new(workspace_path: &Path) -> Self {
+        let workspace_key = file_system::hash_content(workspace_path.to_string_lossy().as_bytes());
+        // Deleted items may be sensitive, so they aren't kept in a directory shared by all users.
+        let root = dirs::data_local_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("workspace-mcp")
+            .join("trash")
+            .join(&workspace_key[..16]);
+
+        Self { root }
+    }
+
+    /// Move `full_path` into the trash and return the new trash entry.
+    pub fn put(&self, full_path: &Path, original_path: &str) -> std::io::Result<TrashEntry> {
+        let is_directory = std::fs::symlink_metadata(full_path)?.is_dir();
+        let deleted_at = chrono::Utc::now();
+        let entry = TrashEntry {
+            id: format!(
+                "{}-{}",
+                deleted_at.format("%Y%m%dT%H%M%S"),
+                &uuid::Uuid::new_v4().simple().to_string()[..8]
+            ),
+            original_path: original_path.to_string(),
+            is_directory,
+            deleted_at: deleted_at.to_rfc3339(),
+        };
+
+        let entry_path = self.root()?.join(&entry.id);
+        std::fs::create_dir(&entry_path)?;
+        let metadata = serde_json::to_vec_pretty(&entry).map_err(std::io::Error::other)?;
+        std::fs::write(entry_path.join(ENTRY_METADATA_FILE_NAME), metadata)?;
+        if let Err(error) =
+            file_system::move_path(full_path, &entry_path.join(ENTRY_ITEM_FILE_NAME))
+        {
+            let _ = std::fs::remove_dir_all(&entry_path);
+            return Err(error);
+        }
+
+        self.purge();
+
+        Ok(entry)
+    }
+
+    /// Move a trashed item back to `full_path`, which must not exist.
+    pub fn restore(&self, id: &str, full_path: &Path) -> std::io::Result<TrashEntry> {
+        let entry = self.get(id)?;
+        if std::fs::symlink_metadata(full_path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "destination already exists",
+            ));
+        }
+
+        let entry_path = self.root()?.join(&entry.id);
+        if let Some(parent) = full_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        file_system::move_path(&entry_path.join(ENTRY_ITEM_FILE_NAME), full_path)?;
+        let _ = std::fs::remove_dir_all(&entry_path);
+
+        Ok(entry)
+    }
+
+    pub fn get(&self, id: &str) -> std::io::Result<TrashEntry> {
+        // Ids are generated by `put()`, anything else could be used to escape the trash root.
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "unknown trash entry",
+            ));
+        }
+
+        let metadata = std::fs::read(self.root()?.join(id).join(ENTRY_METADATA_FILE_NAME))?;
+        serde_json::from_slice(&metadata).map_err(std::io::Error::other)
+    }
+
+    /// List trash entries, most recently deleted first.
+    pub fn list(&self) -> Vec<TrashEntry> {
+        let Ok(read_dir) = self.root().and_then(std::fs::read_dir) else {
+            return Vec::new();
+        };
+        let mut entries: Vec<TrashEntry> = read_dir
+            .filter_map(Result::ok)
+            .filter_map(|dir_entry| self.get(&dir_entry.file_name().to_string_lossy()).ok())
+            .collect();
+        entries.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+
+        entries
+    }
+
+    /// Drop entries beyond [`MAX_TRASH_ENTRIES`] or older than [`TRASH_RETENTION`].
+    fn purge(&self) {
+        let expiry = chrono::Utc::now() - TRASH_RETENTION;
+        for (index, entry) in self.list().into_iter().enumerate() {
+            let is_expired = chrono::DateTime::parse_from_rfc3339(&entry.deleted_at)
+                .map_or(true, |deleted_at| deleted_at < expiry);
+            if index >= MAX_TRASH_ENTRIES || is_expired {
+                tracing::debug!("Purging trash entry `{}`.", entry.id);
+                let _ = std::fs::remove_dir_all(self.root.join(&entry.id));
+            }
+        }
+    }
+
+    /// Create the trash directory if needed, refusing one that another user could have planted
+    /// (e.g. a symlink to a directory they can read) or could read.
+    fn root(&self) -> std::io::Result<&Path> {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+
+            builder.mode(0o700);
+        }
+        builder.create(&self.root)?;
+
+        // The directories of the server, from the one of all workspaces to the one of this one.
+        for path in self.root.ancestors().take(3) {
+            check_private_directory(path)?;
+        }
+
+        Ok(&self.root)
+    }
+}
+
+fn check_private_directory(path: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Err(std::io::Error::other(format!(
+            "the trash directory `{}` is not a directory",
+            path.display()
+        )));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        if metadata.uid() != nix::unistd::getuid().as_raw() {
+            return Err(std::io::Error::other(format!(
+                "the trash directory `{}` is owned by another user",
+                path.display()
+            )));
+        }
+        // Existing directories aren't changed by `DirBuilder`.
+        if metadata.mode() & 0o077 != 0 {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))?;
+        }
+    }
+
+    Ok(())
+}
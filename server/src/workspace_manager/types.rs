@@ -5,9 +5,452 @@ use serde::{Deserialize, Serialize};
 pub struct ListFilesRequest {
     #[schemars(description = "Workspace relative path to list files from.")]
     pub path: Option<String>,
+    #[schemars(description = "List subdirectories content too. Defaults to false.")]
+    pub recursive: Option<bool>,
+    #[schemars(
+        description = "Maximum depth to descend to when `recursive` is set (1 = direct children only). Unlimited by default."
+    )]
+    pub max_depth: Option<usize>,
+    #[schemars(
+        description = "Include hidden files and directories (dot files). Defaults to false."
+    )]
+    pub include_hidden: Option<bool>,
+    #[schemars(
+        description = "Skip files ignored by `.gitignore`, `.ignore` and Git exclude rules. Defaults to true."
+    )]
+    pub respect_gitignore: Option<bool>,
 }
 
 #[derive(Debug, Serialize, schemars::JsonSchema)]
 pub struct ListFilesResponse {
-    pub files: Vec<String>,
+    /// Sorted by path.
+    pub files: Vec<FileEntry>,
+    /// Whether the listing was cut after too many entries.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct FileEntry {
+    /// Workspace relative path.
+    pub path: String,
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+    /// Size in bytes (files only).
+    pub size: Option<u64>,
+    /// RFC 3339 last modification date.
+    pub modified_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ReadFileRequest {
+    #[schemars(description = "Workspace relative path of the file to read.")]
+    pub path: String,
+    #[schemars(
+        description = "First line to read (1-based, inclusive). Defaults to the first line."
+    )]
+    pub start_line: Option<usize>,
+    #[schemars(description = "Last line to read (1-based, inclusive). Defaults to the last line.")]
+    pub end_line: Option<usize>,
+    #[schemars(
+        description = "Maximum number of content bytes to return before truncating. Defaults to 256 KiB."
+    )]
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct ReadFileResponse {
+    pub path: String,
+    /// Either `utf-8` or `binary`. Binary files are never returned as content.
+    pub encoding: String,
+    pub is_binary: bool,
+    /// Hex-encoded SHA-256 digest of the whole file, usable as an edit precondition.
+    pub sha256: String,
+    pub content: String,
+    /// First line included in `content` (1-based).
+    pub start_line: usize,
+    /// Last line included in `content` (1-based, inclusive).
+    pub end_line: usize,
+    pub total_lines: usize,
+    pub truncated: bool,
+    /// Line to pass as `start_line` to continue reading after a truncated response.
+    pub next_start_line: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CreateFileRequest {
+    #[schemars(description = "Workspace relative path of the file to create.")]
+    pub path: String,
+    #[schemars(description = "Content of the new file.")]
+    pub content: String,
+    #[schemars(description = "Create missing parent directories. Defaults to false.")]
+    pub create_parents: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct WriteFileRequest {
+    #[schemars(description = "Workspace relative path of the existing file to overwrite.")]
+    pub path: String,
+    #[schemars(description = "New content replacing the whole file.")]
+    pub content: String,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct WriteFileResponse {
+    pub path: String,
+    pub bytes_written: usize,
+    /// Hex-encoded SHA-256 digest of the new file content.
+    pub sha256: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ReplaceLinesRequest {
+    #[schemars(description = "Workspace relative path of the file to update.")]
+    pub path: String,
+    #[schemars(description = "First line to replace (1-based, inclusive).")]
+    pub start_line: usize,
+    #[schemars(
+        description = "Last line to replace (1-based, inclusive). Use `start_line - 1` to insert before `start_line` without replacing anything."
+    )]
+    pub end_line: usize,
+    #[schemars(
+        description = "Text replacing the lines. An empty string deletes them. A trailing line break is added when missing, unless the file doesn't end with one and the text ends it."
+    )]
+    pub content: String,
+    #[schemars(
+        description = "Current text of the lines being replaced. The update is rejected as a conflict if it differs."
+    )]
+    pub expected_content: Option<String>,
+    #[schemars(
+        description = "SHA-256 of the whole file as returned by `read_file`. The update is rejected as a conflict if it differs."
+    )]
+    pub expected_sha256: Option<String>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct ReplaceLinesResponse {
+    pub path: String,
+    pub lines_removed: usize,
+    pub lines_inserted: usize,
+    pub total_lines: usize,
+    /// Hex-encoded SHA-256 digest of the new file content.
+    pub sha256: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct EditFileRequest {
+    #[schemars(description = "Workspace relative path of the file to edit.")]
+    pub path: String,
+    #[schemars(
+        description = "Exact text to replace, including whitespace and indentation. Must match exactly once unless `replace_all` is set."
+    )]
+    pub old_string: String,
+    #[schemars(description = "Text replacing `old_string`.")]
+    pub new_string: String,
+    #[schemars(description = "Replace every occurrence of `old_string`. Defaults to false.")]
+    pub replace_all: Option<bool>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct EditFileResponse {
+    pub path: String,
+    pub replacements: usize,
+    /// Unified diff of the change.
+    pub diff: String,
+    /// Hex-encoded SHA-256 digest of the new file content.
+    pub sha256: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ApplyPatchRequest {
+    #[schemars(
+        description = "Unified diff (e.g. `git diff` output) of one or more files, with workspace relative paths. Use `/dev/null` as the source of created files and as the destination of deleted files, and `rename from`/`rename to` headers for renames."
+    )]
+    pub patch: String,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct ApplyPatchResponse {
+    pub files: Vec<PatchedFile>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct PatchedFile {
+    pub path: String,
+    /// Source path of a renamed file.
+    pub previous_path: Option<String>,
+    pub operation: PatchOperation,
+    pub hunks: usize,
+    /// Hunks that didn't match exactly at their stated line (shifted or whitespace differences).
+    pub fuzzy_hunks: usize,
+    /// Hex-encoded SHA-256 digest of the new file content, unless the file was deleted.
+    pub sha256: Option<String>,
+    /// Id of the deleted file or of the rename source in the trash, for `restore_deleted`.
+    pub trash_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchOperation {
+    Create,
+    Modify,
+    Delete,
+    Rename,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct DeletePathRequest {
+    #[schemars(description = "Workspace relative path of the file or directory to delete.")]
+    pub path: String,
+    #[schemars(description = "Required to delete a directory and its content. Defaults to false.")]
+    pub recursive: Option<bool>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct DeletePathResponse {
+    pub path: String,
+    pub is_directory: bool,
+    /// Id to pass to `restore_deleted` to undo the deletion.
+    pub trash_id: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RestoreDeletedRequest {
+    #[schemars(description = "Trash id returned by `delete_path` or `list_deleted`.")]
+    pub trash_id: String,
+    #[schemars(
+        description = "Workspace relative path to restore the item to. Defaults to its original path."
+    )]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct RestoreDeletedResponse {
+    pub path: String,
+    pub is_directory: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListDeletedRequest {}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct ListDeletedResponse {
+    /// Most recently deleted first.
+    pub entries: Vec<DeletedEntry>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct DeletedEntry {
+    pub trash_id: String,
+    pub original_path: String,
+    pub is_directory: bool,
+    /// RFC 3339 deletion date.
+    pub deleted_at: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct MovePathRequest {
+    #[schemars(
+        description = "Workspace relative path of the file or directory to move or rename."
+    )]
+    pub source: String,
+    #[schemars(description = "Workspace relative destination path.")]
+    pub destination: String,
+    #[schemars(
+        description = "Replace an existing destination, which is moved to the trash. Defaults to false."
+    )]
+    pub overwrite: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CopyPathRequest {
+    #[schemars(description = "Workspace relative path of the file or directory to copy.")]
+    pub source: String,
+    #[schemars(description = "Workspace relative destination path.")]
+    pub destination: String,
+    #[schemars(
+        description = "Replace an existing destination, which is moved to the trash. Defaults to false."
+    )]
+    pub overwrite: Option<bool>,
+    #[schemars(description = "Required to copy a directory and its content. Defaults to false.")]
+    pub recursive: Option<bool>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct TransferPathResponse {
+    pub source: String,
+    pub destination: String,
+    pub is_directory: bool,
+    /// Trash id of the replaced destination, to pass to `restore_deleted` if needed.
+    pub replaced_trash_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RunCommandRequest {
+    #[schemars(
+        description = "Program to run, looked up in the `PATH`, or the whole command line when `shell` is set."
+    )]
+    pub program: String,
+    #[schemars(description = "Program arguments, passed as is (no shell expansion).")]
+    pub args: Option<Vec<String>>,
+    #[schemars(
+        description = "Run `program` as a command line through the system shell (`sh -c` or `cmd /C`), enabling pipes, redirections and globs. Defaults to false."
+    )]
+    pub shell: Option<bool>,
+    #[schemars(
+        description = "Workspace relative working directory. Defaults to the workspace root."
+    )]
+    pub cwd: Option<String>,
+    #[schemars(description = "Environment variables set on top of the server environment.")]
+    pub env: Option<std::collections::HashMap<String, String>>,
+    #[schemars(
+        description = "Seconds after which the command is killed. Defaults to 120, at most 3600."
+    )]
+    pub timeout_secs: Option<u64>,
+    #[schemars(
+        description = "Maximum number of bytes captured per output stream. The beginning and the end of longer outputs are kept. Defaults to 65536."
+    )]
+    pub max_output_bytes: Option<usize>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct RunCommandResponse {
+    /// `None` when the command was terminated by a signal.
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the command (Unix only).
+    pub signal: Option<i32>,
+    /// Whether the command was killed because it exceeded its timeout.
+    pub timed_out: bool,
+    pub duration_ms: u64,
+    pub stdout: String,
+    pub stderr: String,
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct StartProcessRequest {
+    #[schemars(
+        description = "Program to run, looked up in the `PATH`, or the whole command line when `shell` is set."
+    )]
+    pub program: String,
+    #[schemars(description = "Program arguments, passed as is (no shell expansion).")]
+    pub args: Option<Vec<String>>,
+    #[schemars(
+        description = "Run `program` as a command line through the system shell (`sh -c` or `cmd /C`). Defaults to false."
+    )]
+    pub shell: Option<bool>,
+    #[schemars(
+        description = "Workspace relative working directory. Defaults to the workspace root."
+    )]
+    pub cwd: Option<String>,
+    #[schemars(description = "Environment variables set on top of the server environment.")]
+    pub env: Option<std::collections::HashMap<String, String>>,
+    #[schemars(
+        description = "Run the process in a pseudo-terminal, for interactive programs (REPLs, prompts, etc) and programs behaving differently without a TTY. Stdout and stderr are then merged. Defaults to false."
+    )]
+    pub pty: Option<bool>,
+    #[schemars(description = "Pseudo-terminal rows. Defaults to 24.")]
+    pub rows: Option<u16>,
+    #[schemars(description = "Pseudo-terminal columns. Defaults to 80.")]
+    pub cols: Option<u16>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct StartProcessResponse {
+    pub process_id: String,
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ReadProcessOutputRequest {
+    #[schemars(description = "Id returned by `start_process`.")]
+    pub process_id: String,
+    #[schemars(
+        description = "Output offset to read from, i.e. the `next_cursor` of the previous read. Defaults to 0."
+    )]
+    pub cursor: Option<u64>,
+    #[schemars(description = "Maximum number of bytes to return. Defaults to 65536.")]
+    pub max_bytes: Option<usize>,
+    #[schemars(
+        description = "Milliseconds to wait for new output when there is none yet. Defaults to 0, at most 30000."
+    )]
+    pub wait_ms: Option<u64>,
+    #[schemars(
+        description = "Remove ANSI escape sequences (colors, cursor moves, etc) from the output. Defaults to true."
+    )]
+    pub strip_ansi: Option<bool>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct ReadProcessOutputResponse {
+    pub process_id: String,
+    /// Interleaved stdout and stderr.
+    pub output: String,
+    /// Cursor to pass to the next read.
+    pub next_cursor: u64,
+    /// Bytes that were dropped before they could be read because the output is too large.
+    pub dropped_bytes: u64,
+    pub is_running: bool,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct WriteProcessStdinRequest {
+    #[schemars(description = "Id returned by `start_process`.")]
+    pub process_id: String,
+    #[schemars(description = "Text to write, including line breaks (e.g. `\"y\\n\"`).")]
+    pub input: Option<String>,
+    #[schemars(
+        description = "Keys to press after `input`, mainly for PTY processes: `enter`, `tab`, `escape`, `backspace`, `space`, `up`, `down`, `left`, `right`, `home`, `end`, `delete`, `page_up`, `page_down` or `ctrl+<letter>` (e.g. `ctrl+c`)."
+    )]
+    pub keys: Option<Vec<String>>,
+    #[schemars(
+        description = "Close stdin after writing, e.g. for programs reading until end of input. Defaults to false."
+    )]
+    pub close_stdin: Option<bool>,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct WriteProcessStdinResponse {
+    pub process_id: String,
+    pub bytes_written: usize,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ListProcessesRequest {}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct ListProcessesResponse {
+    /// Oldest first.
+    pub processes: Vec<ProcessInfo>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct KillProcessRequest {
+    #[schemars(description = "Id returned by `start_process`.")]
+    pub process_id: String,
+}
+
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+pub struct ProcessInfo {
+    pub process_id: String,
+    pub command: String,
+    /// Workspace relative working directory.
+    pub cwd: String,
+    pub pid: Option<u32>,
+    pub is_pty: bool,
+    /// RFC 3339 start date.
+    pub started_at: String,
+    pub is_running: bool,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
 }
@@ -1,49 +1,1410 @@
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use rmcp::{
     ErrorData, Json, RoleServer, ServerHandler,
-    handler::server::{router::tool::ToolRouter, tool::Parameters},
-    model::{self, ListPromptsResult, ListResourceTemplatesResult, PaginatedRequestParam},
-    service::RequestContext,
-    tool, tool_handler, tool_router,
+    handler::server::{
+        router::tool::ToolRouter,
+        tool::{Parameters, ToolCallContext},
+    },
+    model::{
+        self, CallToolRequestParam, CallToolResult, ListPromptsResult, ListResourceTemplatesResult,
+        ListToolsResult, PaginatedRequestParam, ProgressNotificationParam, ProgressToken,
+    },
+    service::{Peer, RequestContext},
+    tool, tool_router,
+};
+use tokio::sync::mpsc;
+
+use crate::workspace_manager::{
+    approval::{ApprovalKind, ApprovalQueue},
+    command::{self, CommandSpec},
+    error::{WorkspaceError, WorkspaceResult},
+    file_system, patch,
+    policy::CommandPolicy,
+    process::{ManagedProcess, ProcessRegistry, SessionProcesses},
+    pty,
+    sandbox::Sandbox,
+    telemetry::{Telemetry, TelemetryEvent},
+    trash::Trash,
+    types::{
+        ApplyPatchRequest, ApplyPatchResponse, CopyPathRequest, CreateFileRequest,
+        DeletePathRequest, DeletePathResponse, DeletedEntry, EditFileRequest, EditFileResponse,
+        FileEntry, FileType, KillProcessRequest, ListDeletedRequest, ListDeletedResponse,
+        ListFilesRequest, ListFilesResponse, ListProcessesRequest, ListProcessesResponse,
+        MovePathRequest, PatchOperation, PatchedFile, ProcessInfo, ReadFileRequest,
+        ReadFileResponse, ReadProcessOutputRequest, ReadProcessOutputResponse, ReplaceLinesRequest,
+        ReplaceLinesResponse, RestoreDeletedRequest, RestoreDeletedResponse, RunCommandRequest,
+        RunCommandResponse, StartProcessRequest, StartProcessResponse, TransferPathResponse,
+        WriteFileRequest, WriteFileResponse, WriteProcessStdinRequest, WriteProcessStdinResponse,
+    },
 };
 
-use crate::workspace_manager::types::{ListFilesRequest, ListFilesResponse};
+/// Maximum number of entries returned by `list_files`.
+const MAX_LIST_ENTRIES: usize = 10_000;
+/// Default byte budget for `read_file` content when the request doesn't specify one.
+const DEFAULT_READ_MAX_BYTES: usize = 256 * 1024;
+/// Number of leading bytes inspected for NUL bytes when sniffing binary files (same as Git).
+const BINARY_SNIFF_LENGTH: usize = 8000;
+/// Maximum size of the files tools load in memory to read or edit them.
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 120;
+const MAX_COMMAND_TIMEOUT_SECS: u64 = 3600;
+/// Default byte budget of each `run_command` output stream.
+const DEFAULT_COMMAND_OUTPUT_BYTES: usize = 64 * 1024;
+/// Default byte budget of `read_process_output`.
+const DEFAULT_PROCESS_READ_BYTES: usize = 64 * 1024;
+const MAX_PROCESS_READ_WAIT_MS: u64 = 30_000;
+/// How long `kill_process` waits for the process to exit.
+const KILL_PROCESS_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct WorkspaceManager {
     tool_router: ToolRouter<WorkspaceManager>,
-    workspace_path: PathBuf,
+    sandbox: Sandbox,
+    trash: Trash,
+    /// Background processes of this MCP session, killed once every clone is dropped.
+    processes: Arc<SessionProcesses>,
+    command_policy: Arc<CommandPolicy>,
+    /// Queue of the operations the user must approve in the IDE, when approvals are enabled.
+    approvals: Option<ApprovalQueue>,
+    /// Where tool calls are broadcast to the IDE, when the telemetry endpoint is enabled.
+    telemetry: Option<Telemetry>,
 }
 #[tool_router]
 impl WorkspaceManager {
-    pub fn new(workspace_path_as_string: String) -> Self {
-        let workspace_path = PathBuf::from(workspace_path_as_string);
+    pub fn new(
+        workspace_path_as_string: String,
+        process_registry: ProcessRegistry,
+        command_policy: Arc<CommandPolicy>,
+        approvals: Option<ApprovalQueue>,
+        telemetry: Option<Telemetry>,
+    ) -> Self {
+        let sandbox = Sandbox::new(&PathBuf::from(workspace_path_as_string));
 
         Self {
             tool_router: Self::tool_router(),
-            workspace_path,
+            trash: Trash::new(sandbox.root()),
+            sandbox,
+            processes: Arc::new(SessionProcesses::new(process_registry)),
+            command_policy,
+            approvals,
+            telemetry,
         }
     }
 
-    #[tool(description = "List files in the current workspace directory.")]
+    #[tool(
+        description = "List files in a workspace directory, optionally recursively, with their type, size and last modification date. Hidden and Git-ignored files are skipped by default."
+    )]
     pub fn list_files(
         &self,
-        Parameters(ListFilesRequest { path }): Parameters<ListFilesRequest>,
-    ) -> Result<Json<ListFilesResponse>, String> {
+        Parameters(ListFilesRequest {
+            path,
+            recursive,
+            max_depth,
+            include_hidden,
+            respect_gitignore,
+        }): Parameters<ListFilesRequest>,
+    ) -> Result<Json<ListFilesResponse>, ErrorData> {
         let path: String = path.unwrap_or_else(|| ".".to_string());
-        let full_path = self.workspace_path.join(path);
-        let entries = std::fs::read_dir(full_path).unwrap();
-        let files: Vec<String> = entries
+        let full_path = self.sandbox.resolve(&path)?;
+        let metadata =
+            std::fs::metadata(&full_path).map_err(|error| WorkspaceError::from_io(&path, error))?;
+        if !metadata.is_dir() {
+            return Err(WorkspaceError::NotADirectory { path }.into());
+        }
+
+        let max_depth = match recursive.unwrap_or(false) {
+            true => max_depth,
+            false => Some(1),
+        };
+        let respect_gitignore = respect_gitignore.unwrap_or(true);
+        let walker = ignore::WalkBuilder::new(&full_path)
+            .max_depth(max_depth)
+            .hidden(!include_hidden.unwrap_or(false))
+            .git_ignore(respect_gitignore)
+            .git_global(respect_gitignore)
+            .git_exclude(respect_gitignore)
+            .ignore(respect_gitignore)
+            .parents(respect_gitignore)
+            // Apply `.gitignore` files even when the workspace isn't (yet) a Git repository.
+            .require_git(false)
+            .follow_links(false)
+            .build();
+
+        let mut files: Vec<FileEntry> = Vec::new();
+        let mut truncated = false;
+        // The first entry is the listed directory itself.
+        for entry in walker
             .filter_map(Result::ok)
-            .map(|entry| entry.file_name().into_string().unwrap_or_default())
+            .filter(|entry| entry.depth() > 0)
+        {
+            if files.len() >= MAX_LIST_ENTRIES {
+                truncated = true;
+                break;
+            }
+
+            let metadata = entry.path().symlink_metadata().ok();
+            let file_type = match metadata.as_ref().map(std::fs::Metadata::file_type) {
+                Some(file_type) if file_type.is_symlink() => FileType::Symlink,
+                Some(file_type) if file_type.is_dir() => FileType::Directory,
+                _ => FileType::File,
+            };
+            files.push(FileEntry {
+                path: self.sandbox.relativize(entry.path()),
+                size: metadata
+                    .as_ref()
+                    .filter(|metadata| metadata.is_file())
+                    .map(std::fs::Metadata::len),
+                modified_at: metadata
+                    .and_then(|metadata| metadata.modified().ok())
+                    .map(|modified| chrono::DateTime::<chrono::Utc>::from(modified).to_rfc3339()),
+                file_type,
+            });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Json(ListFilesResponse { files, truncated }))
+    }
+
+    #[tool(
+        description = "Read a workspace file, either entirely or from a start line to an end line (1-based, inclusive). Long reads are truncated to a byte budget and tell you which line to continue from."
+    )]
+    pub fn read_file(
+        &self,
+        Parameters(ReadFileRequest {
+            path,
+            start_line,
+            end_line,
+            max_bytes,
+        }): Parameters<ReadFileRequest>,
+    ) -> Result<Json<ReadFileResponse>, ErrorData> {
+        let full_path = self.sandbox.resolve(&path)?;
+        let bytes = read_bytes(&full_path, &path)?;
+
+        let sha256 = file_system::hash_content(&bytes);
+        let has_nul_byte = bytes[..bytes.len().min(BINARY_SNIFF_LENGTH)].contains(&0);
+        let text = match String::from_utf8(bytes) {
+            Ok(text) if !has_nul_byte => text,
+            _ => {
+                return Ok(Json(ReadFileResponse {
+                    path,
+                    encoding: "binary".to_string(),
+                    is_binary: true,
+                    sha256,
+                    content: String::new(),
+                    start_line: 0,
+                    end_line: 0,
+                    total_lines: 0,
+                    truncated: false,
+                    next_start_line: None,
+                }));
+            }
+        };
+
+        // `split_inclusive` keeps the original line endings so that content can be written back as is.
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        let total_lines = lines.len();
+
+        let start_line = start_line.unwrap_or(1);
+        if start_line == 0 {
+            return Err(WorkspaceError::invalid_argument(
+                "`start_line` is 1-based and must be greater than 0.",
+            )
+            .into());
+        }
+        if start_line > total_lines.max(1) {
+            return Err(WorkspaceError::invalid_argument(format!(
+                "`start_line` ({start_line}) is past the end of `{path}` ({total_lines} lines)."
+            ))
+            .into());
+        }
+        let end_line = end_line.unwrap_or(total_lines).min(total_lines);
+        if total_lines > 0 && end_line < start_line {
+            return Err(WorkspaceError::invalid_argument(format!(
+                "`end_line` ({end_line}) must be greater than or equal to `start_line` ({start_line})."
+            ))
+            .into());
+        }
+
+        let max_bytes = max_bytes.unwrap_or(DEFAULT_READ_MAX_BYTES).max(1);
+        let mut content = String::new();
+        let mut last_line = start_line.saturating_sub(1);
+        let mut truncated = false;
+        let mut is_last_line_cut = false;
+        for (index, line) in lines.iter().enumerate().take(end_line).skip(start_line - 1) {
+            if content.len() + line.len() > max_bytes {
+                truncated = true;
+                // Always return something, even when a single (e.g. minified) line exceeds the budget.
+                if content.is_empty() {
+                    let mut cut = max_bytes;
+                    while !line.is_char_boundary(cut) {
+                        cut -= 1;
+                    }
+                    content.push_str(&line[..cut]);
+                    content.push('\n');
+                    last_line = index + 1;
+                    is_last_line_cut = true;
+                }
+                break;
+            }
+            content.push_str(line);
+            last_line = index + 1;
+        }
+
+        let next_start_line = (truncated && last_line < end_line).then_some(last_line + 1);
+        if truncated {
+            if !content.ends_with('\n') {
+                content.push('\n');
+            }
+            if is_last_line_cut {
+                content.push_str(&format!(
+                    "[truncated: line {last_line} is longer than the {max_bytes} bytes budget and was cut]\n"
+                ));
+            }
+            if let Some(next_start_line) = next_start_line {
+                content.push_str(&format!(
+                    "[truncated: {max_bytes} bytes budget exceeded, continue from line {next_start_line}]\n"
+                ));
+            }
+        }
+
+        Ok(Json(ReadFileResponse {
+            path,
+            encoding: "utf-8".to_string(),
+            is_binary: false,
+            sha256,
+            content,
+            start_line,
+            end_line: last_line,
+            total_lines,
+            truncated,
+            next_start_line,
+        }))
+    }
+
+    #[tool(
+        description = "Create a new workspace file. Fails if the file already exists. Returns the number of bytes written and the SHA-256 of the new content."
+    )]
+    pub fn create_file(
+        &self,
+        Parameters(CreateFileRequest {
+            path,
+            content,
+            create_parents,
+        }): Parameters<CreateFileRequest>,
+    ) -> Result<Json<WriteFileResponse>, ErrorData> {
+        let full_path = self.sandbox.resolve_entry(&path)?;
+        if create_parents.unwrap_or(false)
+            && let Some(parent) = full_path.parent()
+        {
+            std::fs::create_dir_all(parent)
+                .map_err(|error| WorkspaceError::from_io(&path, error))?;
+        }
+
+        file_system::create_atomically(&full_path, content.as_bytes()).map_err(
+            |error| match error.kind() {
+                std::io::ErrorKind::AlreadyExists => WorkspaceError::AlreadyExists {
+                    path: path.clone(),
+                    hint: Some("Use `write_file` to overwrite it."),
+                },
+                _ => WorkspaceError::from_io(&path, error),
+            },
+        )?;
+
+        Ok(Json(WriteFileResponse {
+            sha256: file_system::hash_content(content.as_bytes()),
+            bytes_written: content.len(),
+            path,
+        }))
+    }
+
+    #[tool(
+        description = "Overwrite the whole content of an existing workspace file. Returns the number of bytes written and the SHA-256 of the new content."
+    )]
+    pub async fn write_file(
+        &self,
+        Parameters(WriteFileRequest { path, content }): Parameters<WriteFileRequest>,
+    ) -> Result<Json<WriteFileResponse>, ErrorData> {
+        let full_path = self.sandbox.resolve(&path)?;
+        match std::fs::metadata(&full_path) {
+            Ok(metadata) if metadata.is_dir() => {
+                return Err(WorkspaceError::IsADirectory { path, hint: None }.into());
+            }
+            Ok(_) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Err(WorkspaceError::NotFound {
+                    path,
+                    hint: Some("Use `create_file` to create it."),
+                }
+                .into());
+            }
+            Err(error) => return Err(WorkspaceError::from_io(&path, error).into()),
+        }
+        self.approve_overwrite(&full_path, &path).await?;
+
+        file_system::write_atomically(&full_path, content.as_bytes())
+            .map_err(|error| WorkspaceError::from_io(&path, error))?;
+
+        Ok(Json(WriteFileResponse {
+            sha256: file_system::hash_content(content.as_bytes()),
+            bytes_written: content.len(),
+            path,
+        }))
+    }
+
+    #[tool(
+        description = "Replace a range of lines (1-based, inclusive) of a workspace file with new text. Pass `expected_content` or `expected_sha256` to fail with a conflict instead of overwriting a file that changed since you read it."
+    )]
+    pub async fn replace_lines(
+        &self,
+        Parameters(ReplaceLinesRequest {
+            path,
+            start_line,
+            end_line,
+            content,
+            expected_content,
+            expected_sha256,
+        }): Parameters<ReplaceLinesRequest>,
+    ) -> Result<Json<ReplaceLinesResponse>, ErrorData> {
+        let full_path = self.sandbox.resolve(&path)?;
+        let current = read_text(&full_path, &path)?;
+
+        if let Some(expected_sha256) = expected_sha256
+            && !expected_sha256.eq_ignore_ascii_case(&file_system::hash_content(current.as_bytes()))
+        {
+            return Err(WorkspaceError::Conflict {
+                path,
+                reason: "the file changed since it was read (SHA-256 mismatch). Read it again before updating it.".to_string(),
+            }
+            .into());
+        }
+
+        let lines: Vec<&str> = current.split_inclusive('\n').collect();
+        let total_lines = lines.len();
+        if start_line == 0 {
+            return Err(WorkspaceError::invalid_argument(
+                "`start_line` is 1-based and must be greater than 0.",
+            )
+            .into());
+        }
+        if start_line > total_lines + 1 {
+            return Err(WorkspaceError::invalid_argument(format!(
+                "`start_line` ({start_line}) is past the end of `{path}` ({total_lines} lines)."
+            ))
+            .into());
+        }
+        if end_line + 1 < start_line || end_line > total_lines {
+            return Err(WorkspaceError::invalid_argument(format!(
+                "`end_line` ({end_line}) must be between `start_line - 1` ({}) and the number of lines ({total_lines}).",
+                start_line - 1
+            ))
+            .into());
+        }
+
+        let replaced = &lines[start_line - 1..end_line];
+        if let Some(expected_content) = expected_content {
+            let replaced_content = replaced.concat();
+            if replaced_content.trim_end_matches(['\r', '\n'])
+                != expected_content.trim_end_matches(['\r', '\n'])
+            {
+                return Err(WorkspaceError::Conflict {
+                    path,
+                    reason: format!(
+                        "lines {start_line}-{end_line} don't match `expected_content`. Current content:\n{replaced_content}"
+                    ),
+                }
+                .into());
+            }
+        }
+
+        // Keep the following lines on their own line, using the file line ending style, and keep
+        // the line ending of the file last line when replacing it or appending after it.
+        let mut content = content;
+        let is_followed_by_lines = end_line < total_lines;
+        if !content.is_empty() && !content.ends_with('\n') {
+            let line_ending = match lines.last() {
+                Some(last_line) if !is_followed_by_lines => line_ending_of(last_line),
+                _ if lines.iter().any(|line| line.ends_with("\r\n")) => "\r\n",
+                _ => "\n",
+            };
+            content.push_str(line_ending);
+        }
+        // Appending after a last line without a trailing line break.
+        let mut prefix = lines[..start_line - 1].concat();
+        if start_line > total_lines && !prefix.is_empty() && !prefix.ends_with('\n') {
+            prefix.push('\n');
+        }
+
+        let updated = [prefix.as_str(), &content, &lines[end_line..].concat()].concat();
+        self.approve_edit(&full_path, &path, &current).await?;
+        file_system::write_atomically(&full_path, updated.as_bytes())
+            .map_err(|error| WorkspaceError::from_io(&path, error))?;
+
+        Ok(Json(ReplaceLinesResponse {
+            lines_removed: replaced.len(),
+            lines_inserted: content.split_inclusive('\n').count(),
+            total_lines: updated.split_inclusive('\n').count(),
+            sha256: file_system::hash_content(updated.as_bytes()),
+            path,
+        }))
+    }
+
+    #[tool(
+        description = "Replace an exact `old_string` with `new_string` in a workspace file. Fails if `old_string` is missing or matches more than once (unless `replace_all` is set). Returns a unified diff of the change."
+    )]
+    pub async fn edit_file(
+        &self,
+        Parameters(EditFileRequest {
+            path,
+            old_string,
+            new_string,
+            replace_all,
+        }): Parameters<EditFileRequest>,
+    ) -> Result<Json<EditFileResponse>, ErrorData> {
+        if old_string.is_empty() {
+            return Err(WorkspaceError::invalid_argument("`old_string` must not be empty.").into());
+        }
+        if old_string == new_string {
+            return Err(WorkspaceError::invalid_argument(
+                "`old_string` and `new_string` are identical.",
+            )
+            .into());
+        }
+
+        let full_path = self.sandbox.resolve(&path)?;
+        let current = read_text(&full_path, &path)?;
+
+        let replacements = current.matches(&old_string).count();
+        match replacements {
+            0 => {
+                return Err(WorkspaceError::Conflict {
+                    path,
+                    reason: "`old_string` was not found. Read the file again and copy the text exactly, including whitespace.".to_string(),
+                }
+                .into());
+            }
+            1 => {}
+            _ if !replace_all.unwrap_or(false) => {
+                return Err(WorkspaceError::Conflict {
+                    path,
+                    reason: format!(
+                        "`old_string` matches {replacements} times. Include more surrounding context to make it unique or set `replace_all`."
+                    ),
+                }
+                .into());
+            }
+            _ => {}
+        }
+
+        let updated = current.replace(&old_string, &new_string);
+        self.approve_edit(&full_path, &path, &current).await?;
+        file_system::write_atomically(&full_path, updated.as_bytes())
+            .map_err(|error| WorkspaceError::from_io(&path, error))?;
+
+        let diff = similar::TextDiff::from_lines(&current, &updated)
+            .unified_diff()
+            .header(&format!("a/{path}"), &format!("b/{path}"))
+            .to_string();
+
+        Ok(Json(EditFileResponse {
+            replacements,
+            diff,
+            sha256: file_system::hash_content(updated.as_bytes()),
+            path,
+        }))
+    }
+
+    #[tool(
+        description = "Apply a unified diff touching one or more workspace files, including file creations, deletions and renames. Every hunk is checked against the current content (tolerating shifted line numbers and whitespace differences) and either all files are updated or none. Deleted files and rename sources are moved to the trash and can be recovered with `restore_deleted`."
+    )]
+    pub async fn apply_patch(
+        &self,
+        Parameters(ApplyPatchRequest { patch }): Parameters<ApplyPatchRequest>,
+    ) -> Result<Json<ApplyPatchResponse>, ErrorData> {
+        let file_patches = patch::parse(&patch).map_err(WorkspaceError::invalid_argument)?;
+
+        let mut changes: Vec<file_system::FileChange> = Vec::new();
+        // Full and workspace relative paths to move to the trash, with the index of their file.
+        let mut deletions: Vec<(PathBuf, String, usize)> = Vec::new();
+        // Full and workspace relative paths of the patched files, with the content patched.
+        let mut originals: Vec<(PathBuf, String, String)> = Vec::new();
+        let mut files: Vec<PatchedFile> = Vec::new();
+        let mut patched_paths: HashSet<String> = HashSet::new();
+        for file_patch in file_patches {
+            for path in [&file_patch.old_path, &file_patch.new_path]
+                .into_iter()
+                .flatten()
+                .collect::<HashSet<_>>()
+            {
+                if !patched_paths.insert(path.clone()) {
+                    return Err(WorkspaceError::invalid_argument(format!(
+                        "`{path}` is patched more than once."
+                    ))
+                    .into());
+                }
+            }
+
+            let (original, permissions) = match &file_patch.old_path {
+                Some(old_path) => {
+                    let full_path = self.sandbox.resolve(old_path)?;
+                    let content = read_text(&full_path, old_path)?;
+                    let permissions = std::fs::metadata(&full_path)
+                        .map(|metadata| metadata.permissions())
+                        .ok();
+                    originals.push((full_path, old_path.clone(), content.clone()));
+                    (content, permissions)
+                }
+                None => (String::new(), None),
+            };
+
+            let display_path = file_patch
+                .new_path
+                .as_ref()
+                .or(file_patch.old_path.as_ref())
+                .cloned()
+                .unwrap_or_default();
+            let applied = patch::apply(&original, &file_patch.hunks).map_err(|reason| {
+                WorkspaceError::Conflict {
+                    path: display_path.clone(),
+                    reason,
+                }
+            })?;
+            let sha256 = Some(file_system::hash_content(applied.content.as_bytes()));
+
+            let (operation, previous_path) = match (&file_patch.old_path, &file_patch.new_path) {
+                (None, Some(new_path)) => {
+                    let full_path = self.sandbox.resolve_entry(new_path)?;
+                    if std::fs::symlink_metadata(&full_path).is_ok() {
+                        return Err(WorkspaceError::AlreadyExists {
+                            path: new_path.clone(),
+                            hint: None,
+                        }
+                        .into());
+                    }
+                    changes.push(file_system::FileChange {
+                        path: full_path,
+                        content: Some(applied.content.into_bytes()),
+                        permissions: None,
+                    });
+
+                    (PatchOperation::Create, None)
+                }
+                (Some(old_path), None) => {
+                    if !file_patch.hunks.is_empty() && !applied.content.trim().is_empty() {
+                        return Err(WorkspaceError::Conflict {
+                            path: old_path.clone(),
+                            reason:
+                                "the patch deletes the file but doesn't remove all of its content."
+                                    .to_string(),
+                        }
+                        .into());
+                    }
+                    deletions.push((
+                        self.sandbox.resolve_entry(old_path)?,
+                        old_path.clone(),
+                        files.len(),
+                    ));
+
+                    (PatchOperation::Delete, None)
+                }
+                (Some(old_path), Some(new_path)) if old_path == new_path => {
+                    changes.push(file_system::FileChange {
+                        path: self.sandbox.resolve(new_path)?,
+                        content: Some(applied.content.into_bytes()),
+                        permissions,
+                    });
+
+                    (PatchOperation::Modify, None)
+                }
+                (Some(old_path), Some(new_path)) => {
+                    let full_path = self.sandbox.resolve_entry(new_path)?;
+                    if std::fs::symlink_metadata(&full_path).is_ok() {
+                        return Err(WorkspaceError::AlreadyExists {
+                            path: new_path.clone(),
+                            hint: Some("Rename destinations must not exist."),
+                        }
+                        .into());
+                    }
+                    deletions.push((
+                        self.sandbox.resolve_entry(old_path)?,
+                        old_path.clone(),
+                        files.len(),
+                    ));
+                    changes.push(file_system::FileChange {
+                        path: full_path,
+                        content: Some(applied.content.into_bytes()),
+                        permissions,
+                    });
+
+                    (PatchOperation::Rename, Some(old_path.clone()))
+                }
+                (None, None) => unreachable!("rejected by `patch::parse()`"),
+            };
+
+            files.push(PatchedFile {
+                path: display_path,
+                previous_path,
+                sha256: (!matches!(operation, PatchOperation::Delete))
+                    .then_some(sha256)
+                    .flatten(),
+                operation,
+                hunks: file_patch.hunks.len(),
+                fuzzy_hunks: applied.fuzzy_hunks,
+                trash_id: None,
+            });
+        }
+
+        // Once every file patch applies, so that approvals aren't requested for a failing patch.
+        for file in &files {
+            match (&file.operation, &file.previous_path) {
+                (PatchOperation::Delete, _) => {
+                    self.approve(ApprovalKind::Delete, format!("Delete `{}`", file.path))
+                        .await?;
+                }
+                (PatchOperation::Rename, Some(previous_path)) => {
+                    self.approve(
+                        ApprovalKind::Delete,
+                        format!("Rename `{previous_path}` to `{}`", file.path),
+                    )
+                    .await?;
+                }
+                (PatchOperation::Modify, _) => {
+                    self.approve_overwrite(&self.sandbox.resolve(&file.path)?, &file.path)
+                        .await?;
+                }
+                _ => {}
+            }
+        }
+        for (full_path, path, original) in &originals {
+            ensure_unchanged(full_path, path, original)?;
+        }
+
+        // Deletions are trashed first and restored on failure, like the changes are rolled back, so
+        // no file was changed when this fails.
+        let mut trashed: Vec<(&Path, String)> = Vec::new();
+        let restore_trashed = |trashed: &[(&Path, String)]| {
+            for (full_path, trash_id) in trashed.iter().rev() {
+                let _ = self.trash.restore(trash_id, full_path);
+            }
+        };
+        for (full_path, path, index) in &deletions {
+            match self.trash.put(full_path, path) {
+                Ok(entry) => {
+                    files[*index].trash_id = Some(entry.id.clone());
+                    trashed.push((full_path, entry.id));
+                }
+                Err(error) => {
+                    restore_trashed(&trashed);
+                    return Err(WorkspaceError::from_io(path, error).into());
+                }
+            }
+        }
+        if let Err(error) = file_system::apply_atomically(&changes) {
+            restore_trashed(&trashed);
+            let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+            return Err(WorkspaceError::from_io(&paths.join(", "), error).into());
+        }
+
+        Ok(Json(ApplyPatchResponse { files }))
+    }
+
+    #[tool(
+        description = "Delete a workspace file, or a directory with `recursive` set. Deleted items are moved to a server-managed trash for a limited time and can be recovered with `restore_deleted`."
+    )]
+    pub async fn delete_path(
+        &self,
+        Parameters(DeletePathRequest { path, recursive }): Parameters<DeletePathRequest>,
+    ) -> Result<Json<DeletePathResponse>, ErrorData> {
+        let full_path = self.sandbox.resolve_entry(&path)?;
+        if full_path == self.sandbox.root() {
+            return Err(
+                WorkspaceError::invalid_argument("The workspace root can't be deleted.").into(),
+            );
+        }
+
+        let metadata = std::fs::symlink_metadata(&full_path)
+            .map_err(|error| WorkspaceError::from_io(&path, error))?;
+        if metadata.is_dir() && !recursive.unwrap_or(false) {
+            return Err(WorkspaceError::IsADirectory {
+                path,
+                hint: Some("Set `recursive` to delete it with all its content."),
+            }
+            .into());
+        }
+        let description = match metadata.is_dir() {
+            true => format!("Delete the `{path}` directory and all its content"),
+            false => format!("Delete `{path}`"),
+        };
+        self.approve(ApprovalKind::Delete, description).await?;
+
+        let entry = self
+            .trash
+            .put(&full_path, &path)
+            .map_err(|error| WorkspaceError::from_io(&path, error))?;
+
+        Ok(Json(DeletePathResponse {
+            path,
+            is_directory: entry.is_directory,
+            trash_id: entry.id,
+        }))
+    }
+
+    #[tool(
+        description = "Restore an item deleted with `delete_path`, to its original path or to another one."
+    )]
+    pub fn restore_deleted(
+        &self,
+        Parameters(RestoreDeletedRequest { trash_id, path }): Parameters<RestoreDeletedRequest>,
+    ) -> Result<Json<RestoreDeletedResponse>, ErrorData> {
+        // Trash entries are only missing or unreadable, in which case they can't be restored anyway.
+        let entry = self
+            .trash
+            .get(&trash_id)
+            .map_err(|_| WorkspaceError::NotFound {
+                path: trash_id.clone(),
+                hint: Some("Use `list_deleted` to list the items that can be restored."),
+            })?;
+        let path = path.unwrap_or(entry.original_path);
+
+        let entry = self
+            .trash
+            .restore(&trash_id, &self.sandbox.resolve_entry(&path)?)
+            .map_err(|error| match error.kind() {
+                std::io::ErrorKind::AlreadyExists => WorkspaceError::AlreadyExists {
+                    path: path.clone(),
+                    hint: Some("Pass another `path` to restore it elsewhere."),
+                },
+                _ => WorkspaceError::from_io(&path, error),
+            })?;
+
+        Ok(Json(RestoreDeletedResponse {
+            path,
+            is_directory: entry.is_directory,
+        }))
+    }
+
+    #[tool(description = "List items deleted with `delete_path` that can still be restored.")]
+    pub fn list_deleted(
+        &self,
+        Parameters(ListDeletedRequest {}): Parameters<ListDeletedRequest>,
+    ) -> Result<Json<ListDeletedResponse>, ErrorData> {
+        let entries = self
+            .trash
+            .list()
+            .into_iter()
+            .map(|entry| DeletedEntry {
+                trash_id: entry.id,
+                original_path: entry.original_path,
+                is_directory: entry.is_directory,
+                deleted_at: entry.deleted_at,
+            })
             .collect();
 
-        Ok(Json(ListFilesResponse { files }))
+        Ok(Json(ListDeletedResponse { entries }))
+    }
+
+    #[tool(
+        description = "Move or rename a workspace file or directory. Fails if the destination exists unless `overwrite` is set."
+    )]
+    pub async fn move_path(
+        &self,
+        Parameters(MovePathRequest {
+            source,
+            destination,
+            overwrite,
+        }): Parameters<MovePathRequest>,
+    ) -> Result<Json<TransferPathResponse>, ErrorData> {
+        let (source_path, destination_path, is_directory) =
+            self.prepare_transfer(&source, &destination, overwrite.unwrap_or(false))?;
+        let replaced_trash_id = self.make_room_for(&destination_path, &destination).await?;
+
+        if let Err(error) = file_system::move_path(&source_path, &destination_path) {
+            self.restore_replaced(replaced_trash_id.as_deref(), &destination_path);
+            return Err(WorkspaceError::from_io(&source, error).into());
+        }
+
+        Ok(Json(TransferPathResponse {
+            source,
+            destination,
+            is_directory,
+            replaced_trash_id,
+        }))
+    }
+
+    #[tool(
+        description = "Copy a workspace file, or a directory with `recursive` set, preserving permissions and timestamps. Fails if the destination exists unless `overwrite` is set."
+    )]
+    pub async fn copy_path(
+        &self,
+        Parameters(CopyPathRequest {
+            source,
+            destination,
+            overwrite,
+            recursive,
+        }): Parameters<CopyPathRequest>,
+    ) -> Result<Json<TransferPathResponse>, ErrorData> {
+        let (source_path, destination_path, is_directory) =
+            self.prepare_transfer(&source, &destination, overwrite.unwrap_or(false))?;
+        if is_directory && !recursive.unwrap_or(false) {
+            return Err(WorkspaceError::IsADirectory {
+                path: source,
+                hint: Some("Set `recursive` to copy it with all its content."),
+            }
+            .into());
+        }
+        let replaced_trash_id = self.make_room_for(&destination_path, &destination).await?;
+
+        if let Err(error) = file_system::copy_recursively(&source_path, &destination_path) {
+            let _ = file_system::remove_path(&destination_path);
+            self.restore_replaced(replaced_trash_id.as_deref(), &destination_path);
+            return Err(WorkspaceError::from_io(&source, error).into());
+        }
+
+        Ok(Json(TransferPathResponse {
+            source,
+            destination,
+            is_directory,
+            replaced_trash_id,
+        }))
+    }
+
+    #[tool(
+        description = "Run a CLI command in the workspace and wait for it to finish. The program is run directly with its arguments unless `shell` is set. Returns the exit code, the captured stdout and stderr (truncated in the middle when too long) and whether the command timed out. Output is streamed as progress notifications when the request has a progress token."
+    )]
+    pub async fn run_command(
+        &self,
+        Parameters(RunCommandRequest {
+            program,
+            args,
+            shell,
+            cwd,
+            env,
+            timeout_secs,
+            max_output_bytes,
+        }): Parameters<RunCommandRequest>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Json<RunCommandResponse>, ErrorData> {
+        let cwd = cwd.unwrap_or_else(|| ".".to_string());
+        let command = self
+            .build_command(&program, args, shell, &cwd, env)
+            .await?
+            .to_command();
+
+        let timeout = Duration::from_secs(
+            timeout_secs
+                .unwrap_or(DEFAULT_COMMAND_TIMEOUT_SECS)
+                .clamp(1, MAX_COMMAND_TIMEOUT_SECS),
+        );
+        let timeout = match self.command_policy.max_runtime() {
+            Some(max_runtime) => timeout.min(max_runtime),
+            None => timeout,
+        };
+        let max_output_bytes = max_output_bytes
+            .unwrap_or(DEFAULT_COMMAND_OUTPUT_BYTES)
+            .max(1);
+
+        let (progress_sender, progress_task) = match context.meta.get_progress_token() {
+            Some(progress_token) => {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                let task = tokio::spawn(notify_output_progress(
+                    context.peer.clone(),
+                    progress_token,
+                    receiver,
+                ));
+                (Some(sender), Some(task))
+            }
+            None => (None, None),
+        };
+        let output = command::run(
+            command,
+            timeout,
+            max_output_bytes,
+            &context.ct,
+            |_, chunk| {
+                if let Some(sender) = &progress_sender {
+                    let _ = sender.send(chunk.to_vec());
+                }
+            },
+        )
+        .await;
+        // Let the last notifications out before the result.
+        drop(progress_sender);
+        if let Some(progress_task) = progress_task {
+            let _ = progress_task.await;
+        }
+        let output = output.map_err(|error| spawn_error(&program, error))?;
+
+        Ok(Json(RunCommandResponse {
+            exit_code: output.exit_code,
+            signal: output.signal,
+            timed_out: output.timed_out,
+            duration_ms: u64::try_from(output.duration.as_millis()).unwrap_or(u64::MAX),
+            stdout: output.stdout.to_text(),
+            stderr: output.stderr.to_text(),
+            stdout_truncated: output.stdout.is_truncated(),
+            stderr_truncated: output.stderr.is_truncated(),
+        }))
+    }
+
+    #[tool(
+        description = "Start a long-running or interactive CLI command (dev server, watcher, REPL, etc) in the background, optionally in a pseudo-terminal, and return its process id. Use `read_process_output` to follow its output, `write_process_stdin` to interact with it and `kill_process` to stop it. Background processes are killed when the session ends."
+    )]
+    pub async fn start_process(
+        &self,
+        Parameters(StartProcessRequest {
+            program,
+            args,
+            shell,
+            cwd,
+            env,
+            pty,
+            rows,
+            cols,
+        }): Parameters<StartProcessRequest>,
+    ) -> Result<Json<StartProcessResponse>, ErrorData> {
+        let description = match &args {
+            Some(args) if !args.is_empty() => format!("{program} {}", args.join(" ")),
+            _ => program.clone(),
+        };
+        let cwd = cwd.unwrap_or_else(|| ".".to_string());
+        let spec = self.build_command(&program, args, shell, &cwd, env).await?;
+
+        let registry = self.processes.registry();
+        // Checked after the approval of the command, since other processes may have started since.
+        registry.check_capacity(&self.processes.id)?;
+        let process = match pty.unwrap_or(false) {
+            true => {
+                let size = portable_pty::PtySize {
+                    rows: rows.unwrap_or(pty::DEFAULT_ROWS).max(1),
+                    cols: cols.unwrap_or(pty::DEFAULT_COLS).max(1),
+                    ..Default::default()
+                };
+                registry.spawn_pty(&self.processes.id, &spec, description, cwd, size)
+            }
+            false => registry.spawn(&self.processes.id, &spec, description, cwd),
+        }
+        .map_err(|error| spawn_error(&program, error))?;
+        if let Some(max_runtime) = self.command_policy.max_runtime() {
+            tokio::spawn({
+                let process = process.clone();
+                async move {
+                    if process.wait(max_runtime).await.is_none() {
+                        process.kill();
+                    }
+                }
+            });
+        }
+
+        Ok(Json(StartProcessResponse {
+            process_id: process.id.clone(),
+            pid: process.pid,
+        }))
+    }
+
+    #[tool(
+        description = "Read the output (interleaved stdout and stderr) of a background process from a cursor, optionally waiting for new output. Also tells whether the process is still running and its exit code."
+    )]
+    pub async fn read_process_output(
+        &self,
+        Parameters(ReadProcessOutputRequest {
+            process_id,
+            cursor,
+            max_bytes,
+            wait_ms,
+            strip_ansi,
+        }): Parameters<ReadProcessOutputRequest>,
+    ) -> Result<Json<ReadProcessOutputResponse>, ErrorData> {
+        let process = self.get_process(&process_id)?;
+        let wait = Duration::from_millis(wait_ms.unwrap_or(0).min(MAX_PROCESS_READ_WAIT_MS));
+        let slice = process
+            .read_output(
+                cursor.unwrap_or(0),
+                max_bytes.unwrap_or(DEFAULT_PROCESS_READ_BYTES).max(1),
+                wait,
+            )
+            .await;
+        let exit = process.exit();
+
+        Ok(Json(ReadProcessOutputResponse {
+            process_id,
+            output: match strip_ansi.unwrap_or(true) {
+                true => pty::strip_ansi(&slice.text),
+                false => slice.text,
+            },
+            next_cursor: slice.next_cursor,
+            dropped_bytes: slice.dropped_bytes,
+            is_running: exit.is_none(),
+            exit_code: exit.and_then(|exit| exit.exit_code),
+            signal: exit.and_then(|exit| exit.signal),
+        }))
     }
+
+    #[tool(
+        description = "Write text and/or keystrokes (enter, arrows, ctrl+c, etc) to the stdin of a background process."
+    )]
+    pub async fn write_process_stdin(
+        &self,
+        Parameters(WriteProcessStdinRequest {
+            process_id,
+            input,
+            keys,
+            close_stdin,
+        }): Parameters<WriteProcessStdinRequest>,
+    ) -> Result<Json<WriteProcessStdinResponse>, ErrorData> {
+        let mut bytes = input.unwrap_or_default().into_bytes();
+        for key in keys.unwrap_or_default() {
+            let sequence = pty::key_sequence(&key).ok_or_else(|| {
+                WorkspaceError::invalid_argument(format!("`{key}` is not a supported key."))
+            })?;
+            bytes.extend(sequence);
+        }
+
+        let process = self.get_process(&process_id)?;
+        process
+            .write_stdin(&bytes, close_stdin.unwrap_or(false))
+            .await
+            .map_err(|error| match error.kind() {
+                std::io::ErrorKind::BrokenPipe => WorkspaceError::Conflict {
+                    path: process_id.clone(),
+                    reason: "the process stdin is closed or the process exited.".to_string(),
+                },
+                std::io::ErrorKind::TimedOut => WorkspaceError::Conflict {
+                    path: process_id.clone(),
+                    reason: "the process didn't read its input in time, part of it may still be \
+                             delivered later."
+                        .to_string(),
+                },
+                _ => WorkspaceError::from_io(&process_id, error),
+            })?;
+
+        Ok(Json(WriteProcessStdinResponse {
+            process_id,
+            bytes_written: bytes.len(),
+        }))
+    }
+
+    #[tool(description = "List the background processes started in this session.")]
+    pub fn list_processes(
+        &self,
+        Parameters(ListProcessesRequest {}): Parameters<ListProcessesRequest>,
+    ) -> Result<Json<ListProcessesResponse>, ErrorData> {
+        let processes = self
+            .processes
+            .registry()
+            .list(&self.processes.id)
+            .iter()
+            .map(|process| process_info(process))
+            .collect();
+
+        Ok(Json(ListProcessesResponse { processes }))
+    }
+
+    #[tool(
+        description = "Kill a background process along with the processes it spawned. Its output can still be read afterwards."
+    )]
+    pub async fn kill_process(
+        &self,
+        Parameters(KillProcessRequest { process_id }): Parameters<KillProcessRequest>,
+    ) -> Result<Json<ProcessInfo>, ErrorData> {
+        let process = self.get_process(&process_id)?;
+        process.kill();
+        process.wait(KILL_PROCESS_TIMEOUT).await;
+
+        Ok(Json(process_info(&process)))
+    }
+}
+
+impl WorkspaceManager {
+    /// Build a command run directly or through the system shell, in the `cwd` workspace directory.
+    async fn build_command(
+        &self,
+        program: &str,
+        args: Option<Vec<String>>,
+        shell: Option<bool>,
+        cwd: &str,
+        env: Option<HashMap<String, String>>,
+    ) -> WorkspaceResult<CommandSpec> {
+        let cwd_path = self.sandbox.resolve(cwd)?;
+        if !cwd_path.is_dir() {
+            return Err(WorkspaceError::NotADirectory {
+                path: cwd.to_string(),
+            });
+        }
+
+        let args = args.unwrap_or_default();
+        let (program, args) = match shell.unwrap_or(false) {
+            true if !args.is_empty() => {
+                return Err(WorkspaceError::invalid_argument(
+                    "`args` can't be used with `shell`: include them in the `program` command line.",
+                ));
+            }
+            true if cfg!(windows) => (
+                "cmd".to_string(),
+                vec!["/C".to_string(), program.to_string()],
+            ),
+            true => (
+                "sh".to_string(),
+                vec!["-c".to_string(), program.to_string()],
+            ),
+            false => (program.to_string(), args),
+        };
+
+        // Every spawned command goes through here, so that none escapes the policy.
+        let program_check = self.command_policy.check_program(&program);
+        let command_line = [program.as_str()]
+            .into_iter()
+            .chain(args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let spec = self.command_policy.apply(CommandSpec {
+            program,
+            args,
+            cwd: cwd_path,
+            env: env.unwrap_or_default(),
+            removed_env: Vec::new(),
+        })?;
+        if let Err(error) = program_check {
+            // The user can still allow programs off the allowlist, one command at a time.
+            let Some(approvals) = &self.approvals else {
+                return Err(error);
+            };
+            approvals
+                .request(
+                    ApprovalKind::Command,
+                    format!("Run `{command_line}` in `{cwd}`"),
+                )
+                .await?;
+        }
+
+        Ok(spec)
+    }
+
+    /// Wait for the user to approve a dangerous operation, when approvals are enabled.
+    async fn approve(&self, kind: ApprovalKind, description: String) -> WorkspaceResult<()> {
+        match &self.approvals {
+            Some(approvals) => approvals.request(kind, description).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn approve_overwrite(&self, full_path: &Path, path: &str) -> WorkspaceResult<()> {
+        if let Some(approvals) = &self.approvals
+            && let Ok(relative_path) = full_path.strip_prefix(self.sandbox.root())
+            && approvals.is_safe_overwrite(relative_path)
+        {
+            return Ok(());
+        }
+
+        self.approve(ApprovalKind::Overwrite, format!("Overwrite `{path}`"))
+            .await
+    }
+
+    /// Approve overwriting `full_path` with content computed from its `current` content, failing if
+    /// it changed while the approval was pending (e.g. edited by the user in the IDE).
+    async fn approve_edit(
+        &self,
+        full_path: &Path,
+        path: &str,
+        current: &str,
+    ) -> WorkspaceResult<()> {
+        self.approve_overwrite(full_path, path).await?;
+
+        ensure_unchanged(full_path, path, current)
+    }
+
+    fn get_process(&self, process_id: &str) -> WorkspaceResult<Arc<ManagedProcess>> {
+        self.processes
+            .registry()
+            .get(&self.processes.id, process_id)
+            .ok_or_else(|| WorkspaceError::NotFound {
+                path: process_id.to_string(),
+                hint: Some("Use `list_processes` to list the processes of this session."),
+            })
+    }
+
+    /// Validate a move or copy, returning the full source and destination paths and whether the
+    /// source is a directory.
+    fn prepare_transfer(
+        &self,
+        source: &str,
+        destination: &str,
+        overwrite: bool,
+    ) -> WorkspaceResult<(PathBuf, PathBuf, bool)> {
+        let source_path = self.sandbox.resolve_entry(source)?;
+        let destination_path = self.sandbox.resolve_entry(destination)?;
+
+        let metadata = std::fs::symlink_metadata(&source_path)
+            .map_err(|error| WorkspaceError::from_io(source, error))?;
+        if source_path == self.sandbox.root() {
+            return Err(WorkspaceError::invalid_argument(
+                "The workspace root can't be moved or copied.",
+            ));
+        }
+        if destination_path.starts_with(&source_path) {
+            return Err(WorkspaceError::invalid_argument(format!(
+                "Cannot move or copy `{source}` into itself (`{destination}`)."
+            )));
+        }
+        // Replacing the destination would trash the source along with it.
+        if source_path.starts_with(&destination_path) {
+            return Err(WorkspaceError::invalid_argument(format!(
+                "Cannot move or copy `{source}` over its parent directory `{destination}`."
+            )));
+        }
+        if !overwrite && std::fs::symlink_metadata(&destination_path).is_ok() {
+            return Err(WorkspaceError::AlreadyExists {
+                path: destination.to_string(),
+                hint: Some("Set `overwrite` to replace it."),
+            });
+        }
+
+        Ok((source_path, destination_path, metadata.is_dir()))
+    }
+
+    /// Move an existing `full_path` to the trash once approved, returning its trash id, or create
+    /// its missing parent directories.
+    async fn make_room_for(&self, full_path: &Path, path: &str) -> WorkspaceResult<Option<String>> {
+        if std::fs::symlink_metadata(full_path).is_ok() {
+            self.approve_overwrite(full_path, path).await?;
+            return self
+                .trash
+                .put(full_path, path)
+                .map(|entry| Some(entry.id))
+                .map_err(|error| WorkspaceError::from_io(path, error));
+        }
+
+        if let Some(parent) = full_path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|error| WorkspaceError::from_io(path, error))?;
+        }
+
+        Ok(None)
+    }
+
+    /// Put back what [`Self::make_room_for`] trashed once the operation replacing it failed.
+    fn restore_replaced(&self, replaced_trash_id: Option<&str>, full_path: &Path) {
+        if let Some(trash_id) = replaced_trash_id
+            && let Err(error) = self.trash.restore(trash_id, full_path)
+        {
+            // It stays in the trash, listed by `list_deleted`.
+            tracing::warn!("Cannot restore the trash entry `{trash_id}`: {error}.");
+        }
+    }
+}
+
+fn spawn_error(program: &str, error: std::io::Error) -> WorkspaceError {
+    match error.kind() {
+        std::io::ErrorKind::NotFound => WorkspaceError::NotFound {
+            path: program.to_string(),
+            hint: Some("Check that the program is installed and in the `PATH`."),
+        },
+        _ => WorkspaceError::from_io(program, error),
+    }
+}
+
+fn process_info(process: &ManagedProcess) -> ProcessInfo {
+    let exit = process.exit();
+
+    ProcessInfo {
+        process_id: process.id.clone(),
+        command: process.command.clone(),
+        cwd: process.cwd.clone(),
+        pid: process.pid,
+        is_pty: process.is_pty,
+        started_at: process.started_at.to_rfc3339(),
+        is_running: exit.is_none(),
+        exit_code: exit.and_then(|exit| exit.exit_code),
+        signal: exit.and_then(|exit| exit.signal),
+    }
+}
+
+/// Send command output chunks to the client as progress notifications, with the number of bytes
+/// output so far as progress, until the command ends.
+async fn notify_output_progress(
+    peer: Peer<RoleServer>,
+    progress_token: ProgressToken,
+    mut receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+) {
+    let mut progress = 0;
+    while let Some(mut chunk) = receiver.recv().await {
+        // Coalesce chunks read while the previous notification was being sent.
+        while let Ok(next_chunk) = receiver.try_recv() {
+            chunk.extend(next_chunk);
+        }
+        progress += chunk.len();
+
+        let notification = ProgressNotificationParam {
+            progress_token: progress_token.clone(),
+            progress: progress as f64,
+            total: None,
+            message: Some(String::from_utf8_lossy(&chunk).into_owned()),
+        };
+        if peer.notify_progress(notification).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Read a whole file, refusing files over [`MAX_FILE_SIZE`].
+fn read_bytes(full_path: &Path, path: &str) -> WorkspaceResult<Vec<u8>> {
+    let metadata =
+        std::fs::metadata(full_path).map_err(|error| WorkspaceError::from_io(path, error))?;
+    if metadata.is_dir() {
+        return Err(WorkspaceError::IsADirectory {
+            path: path.to_string(),
+            hint: Some("Use `list_files` to list its content."),
+        });
+    }
+    if metadata.len() > MAX_FILE_SIZE {
+        return Err(WorkspaceError::TooLarge {
+            path: path.to_string(),
+            size: metadata.len(),
+            limit: MAX_FILE_SIZE,
+        });
+    }
+
+    std::fs::read(full_path).map_err(|error| WorkspaceError::from_io(path, error))
+}
+
+/// Read a whole file that is about to be edited, which must be UTF-8 text.
+fn read_text(full_path: &Path, path: &str) -> WorkspaceResult<String> {
+    String::from_utf8(read_bytes(full_path, path)?).map_err(|_| {
+        WorkspaceError::invalid_argument(format!(
+            "`{path}` is not a UTF-8 text file and can't be edited."
+        ))
+    })
+}
+
+/// Fail if the file no longer has the `expected` content it had when it was read.
+fn ensure_unchanged(full_path: &Path, path: &str, expected: &str) -> WorkspaceResult<()> {
+    if read_text(full_path, path)? != expected {
+        return Err(WorkspaceError::Conflict {
+            path: path.to_string(),
+            reason:
+                "the file changed while waiting for the approval. Read it again before updating it."
+                    .to_string(),
+        });
+    }
+
+    Ok(())
 }
 
-#[tool_handler]
+fn line_ending_of(line: &str) -> &'static str {
+    if line.ends_with("\r\n") {
+        "\r\n"
+    } else if line.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+// Implemented by hand rather than with `#[tool_handler]` to report tool calls to the telemetry.
 impl ServerHandler for WorkspaceManager {
     fn get_info(&self) -> model::ServerInfo {
         model::ServerInfo {
@@ -58,6 +1419,63 @@ impl ServerHandler for WorkspaceManager {
         }
     }
 
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let Some(telemetry) = &self.telemetry else {
+            let tool_call_context = ToolCallContext::new(self, request, context);
+            return self.tool_router.call(tool_call_context).await;
+        };
+
+        let call_id = context.id.to_string();
+        // The HTTP session id is the one known to the client, when there is one.
+        let session_id = context
+            .extensions
+            .get::<axum::http::request::Parts>()
+            .and_then(|parts| parts.headers.get("mcp-session-id"))
+            .and_then(|value| value.to_str().ok())
+            .map_or_else(|| self.processes.id.clone(), str::to_string);
+        let tool = request.name.to_string();
+        telemetry.send(TelemetryEvent::ToolCallStarted {
+            call_id: call_id.clone(),
+            session_id: session_id.clone(),
+            tool: tool.clone(),
+            arguments: request.arguments.clone(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+        });
+
+        let started_at = Instant::now();
+        let tool_call_context = ToolCallContext::new(self, request, context);
+        let result = self.tool_router.call(tool_call_context).await;
+
+        telemetry.send(TelemetryEvent::ToolCallFinished {
+            call_id,
+            session_id,
+            tool,
+            duration_ms: u64::try_from(started_at.elapsed().as_millis()).unwrap_or(u64::MAX),
+            result: result
+                .as_ref()
+                .ok()
+                .map(|result| match &result.structured_content {
+                    Some(structured_content) => structured_content.clone(),
+                    None => serde_json::to_value(result).unwrap_or_default(),
+                }),
+            error: result.as_ref().err().cloned(),
+        });
+
+        result
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, ErrorData> {
+        Ok(ListToolsResult::with_all_items(self.tool_router.list_all()))
+    }
+
     async fn list_prompts(
         &self,
         _request: Option<PaginatedRequestParam>,
@@ -80,3 +1498,199 @@ impl ServerHandler for WorkspaceManager {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace_manager() -> (tempfile::TempDir, WorkspaceManager) {
+        workspace_manager_with(None)
+    }
+
+    fn workspace_manager_with(
+        approvals: Option<ApprovalQueue>,
+    ) -> (tempfile::TempDir, WorkspaceManager) {
+        let directory = tempfile::tempdir().unwrap();
+        let workspace_manager = WorkspaceManager::new(
+            directory.path().to_string_lossy().into_owned(),
+            ProcessRegistry::default(),
+            Arc::new(CommandPolicy::default()),
+            approvals,
+            None,
+        );
+
+        (directory, workspace_manager)
+    }
+
+    /// Replace the lines of a file with `content` and return its new content.
+    async fn replace_lines(
+        content: &str,
+        start_line: usize,
+        end_line: usize,
+        replacement: &str,
+    ) -> String {
+        let (directory, workspace_manager) = workspace_manager();
+        std::fs::write(directory.path().join("file"), content).unwrap();
+        workspace_manager
+            .replace_lines(Parameters(ReplaceLinesRequest {
+                path: "file".to_string(),
+                start_line,
+                end_line,
+                content: replacement.to_string(),
+                expected_content: None,
+                expected_sha256: None,
+            }))
+            .await
+            .unwrap();
+
+        std::fs::read_to_string(directory.path().join("file")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replaces_the_last_line_keeping_its_line_ending() {
+        assert_eq!(replace_lines("a\nb\n", 2, 2, "B").await, "a\nB\n");
+        assert_eq!(replace_lines("a\nb", 2, 2, "B").await, "a\nB");
+        assert_eq!(replace_lines("a\nb", 2, 2, "B\n").await, "a\nB\n");
+    }
+
+    #[tokio::test]
+    async fn appends_after_a_last_line_without_line_ending() {
+        assert_eq!(replace_lines("a", 2, 1, "b").await, "a\nb");
+        assert_eq!(replace_lines("a\n", 2, 1, "b").await, "a\nb\n");
+    }
+
+    #[tokio::test]
+    async fn uses_the_line_ending_of_crlf_files() {
+        assert_eq!(
+            replace_lines("a\r\nb\r\nc\r\n", 2, 2, "B").await,
+            "a\r\nB\r\nc\r\n"
+        );
+        assert_eq!(replace_lines("a\r\nb\r\n", 2, 2, "B").await, "a\r\nB\r\n");
+    }
+
+    #[tokio::test]
+    async fn inserts_lines_when_end_line_precedes_start_line() {
+        assert_eq!(replace_lines("a\nc\n", 2, 1, "b").await, "a\nb\nc\n");
+        assert_eq!(replace_lines("b\n", 1, 0, "a\n").await, "a\nb\n");
+    }
+
+    #[tokio::test]
+    async fn refuses_transfers_over_a_parent_directory() {
+        let (directory, workspace_manager) = workspace_manager();
+        std::fs::create_dir(directory.path().join("a")).unwrap();
+        std::fs::write(directory.path().join("a/b.txt"), "b").unwrap();
+
+        let error = workspace_manager
+            .move_path(Parameters(MovePathRequest {
+                source: "a/b.txt".to_string(),
+                destination: "a".to_string(),
+                overwrite: Some(true),
+            }))
+            .await
+            .err()
+            .expect("the destination contains the source");
+        assert_eq!(error.data.unwrap()["kind"], "invalid_argument");
+        assert!(directory.path().join("a/b.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restores_the_replaced_destination_of_failed_copies() {
+        let (directory, workspace_manager) = workspace_manager();
+        std::fs::create_dir(directory.path().join("source")).unwrap();
+        // Sockets can't be copied.
+        let _listener =
+            std::os::unix::net::UnixListener::bind(directory.path().join("source/socket")).unwrap();
+        std::fs::write(directory.path().join("destination"), "kept").unwrap();
+
+        workspace_manager
+            .copy_path(Parameters(CopyPathRequest {
+                source: "source".to_string(),
+                destination: "destination".to_string(),
+                overwrite: Some(true),
+                recursive: Some(true),
+            }))
+            .await
+            .err()
+            .expect("the socket can't be copied");
+        assert_eq!(
+            std::fs::read_to_string(directory.path().join("destination")).unwrap(),
+            "kept"
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_replacing_lines_that_changed() {
+        let (directory, workspace_manager) = workspace_manager();
+        std::fs::write(directory.path().join("file"), "a\nb\n").unwrap();
+        let request = |expected_content: Option<&str>, expected_sha256: Option<&str>| {
+            Parameters(ReplaceLinesRequest {
+                path: "file".to_string(),
+                start_line: 2,
+                end_line: 2,
+                content: "B".to_string(),
+                expected_content: expected_content.map(str::to_string),
+                expected_sha256: expected_sha256.map(str::to_string),
+            })
+        };
+
+        for (expected_content, expected_sha256) in [
+            (Some("x"), None),
+            (None, Some(file_system::hash_content(b"a\nx\n"))),
+        ] {
+            let error = workspace_manager
+                .replace_lines(request(expected_content, expected_sha256.as_deref()))
+                .await
+                .err()
+                .expect("the lines changed");
+            assert_eq!(error.data.unwrap()["kind"], "conflict");
+        }
+        assert_eq!(
+            std::fs::read_to_string(directory.path().join("file")).unwrap(),
+            "a\nb\n"
+        );
+
+        workspace_manager
+            .replace_lines(request(
+                Some("b"),
+                Some(&file_system::hash_content(b"a\nb\n")),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(directory.path().join("file")).unwrap(),
+            "a\nB\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_edits_of_files_changed_while_waiting_for_the_approval() {
+        let approvals = ApprovalQueue::new(Duration::from_secs(10), globset::GlobSet::empty());
+        let (directory, workspace_manager) = workspace_manager_with(Some(approvals.clone()));
+        let file_path = directory.path().join("file");
+        std::fs::write(&file_path, "a\n").unwrap();
+
+        let edit = workspace_manager.edit_file(Parameters(EditFileRequest {
+            path: "file".to_string(),
+            old_string: "a".to_string(),
+            new_string: "b".to_string(),
+            replace_all: None,
+        }));
+        // The user edits the file before approving the edit.
+        let approve = async {
+            loop {
+                if let Some(approval) = approvals.list().first() {
+                    std::fs::write(&file_path, "user edit\n").unwrap();
+                    approvals.resolve(&approval.id, true);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let (result, ()) = tokio::join!(edit, approve);
+
+        let error = result.err().expect("the file changed");
+        assert_eq!(error.data.unwrap()["kind"], "conflict");
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "user edit\n");
+    }
+}